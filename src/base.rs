@@ -1,5 +1,9 @@
-use std::sync::{Arc, Weak};
+use std::{
+    fmt,
+    sync::{Arc, Weak},
+};
 
+use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 use tokio::time::Instant;
 
@@ -15,32 +19,80 @@ pub enum Executable {
     Flow(Weak<Flow>),
     Procedure(Weak<Procedure>),
     Selection(Vec<Weak<Flow>>),
+    Inclusive(Vec<Weak<Flow>>),
 }
 
+// compares the pointers only, so executables of dropped definitions can
+// still be compared
 impl PartialEq for Executable {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
-            (Executable::Node(node1), Executable::Node(node2)) => {
-                Arc::ptr_eq(&node1.upgrade().unwrap(), &node2.upgrade().unwrap())
+            (Executable::Node(node1), Executable::Node(node2)) => Weak::ptr_eq(node1, node2),
+            (Executable::Flow(flow1), Executable::Flow(flow2)) => Weak::ptr_eq(flow1, flow2),
+            (Executable::Procedure(procedure1), Executable::Procedure(procedure2)) => {
+                Weak::ptr_eq(procedure1, procedure2)
             }
-            (Executable::Flow(flow1), Executable::Flow(flow2)) => {
-                Arc::ptr_eq(&flow1.upgrade().unwrap(), &flow2.upgrade().unwrap())
-            }
-            (Executable::Procedure(procedure1), Executable::Procedure(procedure2)) => Arc::ptr_eq(
-                &procedure1.upgrade().unwrap(),
-                &procedure2.upgrade().unwrap(),
-            ),
-            (Executable::Selection(flows1), Executable::Selection(flows2)) => {
+            (Executable::Selection(flows1), Executable::Selection(flows2))
+            | (Executable::Inclusive(flows1), Executable::Inclusive(flows2)) => {
                 flows1.len() == flows2.len()
-                    && flows1.iter().zip(flows2).all(|(flow1, flow2)| {
-                        Arc::ptr_eq(&flow1.upgrade().unwrap(), &flow2.upgrade().unwrap())
-                    })
+                    && flows1
+                        .iter()
+                        .zip(flows2)
+                        .all(|(flow1, flow2)| Weak::ptr_eq(flow1, flow2))
             }
             _ => false,
         }
     }
 }
 
+// stable identity of a procedure definition
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ProcedureKey {
+    pub name: String,
+    pub version: u32,
+}
+
+// an element of a procedure by name
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Element {
+    Procedure,
+    Node(String),
+    Flow(String),
+    Selection(Vec<String>),
+    Inclusive(Vec<String>),
+}
+
+// stable reference to an executable, which outlives the graph and can be
+// persisted, logged or sent elsewhere and resolved back by the procedure
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ExecutableKey {
+    pub procedure: ProcedureKey,
+    pub element: Element,
+}
+
+impl fmt::Display for ProcedureKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.name, self.version)
+    }
+}
+
+impl fmt::Display for ExecutableKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.element {
+            Element::Procedure => write!(f, "{}", self.procedure),
+            Element::Node(name) => write!(f, "{}/node:{}", self.procedure, name),
+            Element::Flow(name) => write!(f, "{}/flow:{}", self.procedure, name),
+            Element::Selection(names) => {
+                write!(f, "{}/selection:{}", self.procedure, names.join(","))
+            }
+            Element::Inclusive(names) => {
+                write!(f, "{}/inclusive:{}", self.procedure, names.join(","))
+            }
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Next {
     // do nothing and will execute the current node again
@@ -62,21 +114,35 @@ pub enum Next {
     Bubble,
 }
 
+// no flow of a gateway matched, the flows share the gateway as their source,
+// which names it in the error
+async fn no_next_node(flows: &[Weak<Flow>], cursor: &Arc<RwLock<Cursor>>) -> Error {
+    let flow = flows.first().and_then(Weak::upgrade);
+    let procedure = cursor.read().await.procedure().upgrade();
+    Error::NoNextNode {
+        procedure: procedure.map(|p| p.name.clone()).unwrap_or_default(),
+        node: flow
+            .and_then(|flow| flow.source().upgrade())
+            .map(|node| node.name.clone())
+            .unwrap_or_default(),
+    }
+}
+
 impl Executable {
     pub async fn execute(&self, cursor: Arc<RwLock<Cursor>>) -> Result<Next, Error> {
         match self {
             Executable::Node(node) => {
                 if let Some(node) = node.upgrade() {
-                    node.execute(cursor.clone()).await?;
+                    node.execute(cursor.clone()).await
                 } else {
-                    return Err(Error::Canceled);
+                    Err(Error::Canceled)
                 }
             }
             Executable::Flow(flow) => {
                 if let Some(flow) = flow.upgrade() {
-                    flow.execute(cursor.clone()).await?;
+                    flow.execute(cursor.clone()).await
                 } else {
-                    return Err(Error::Canceled);
+                    Err(Error::Canceled)
                 }
             }
             Executable::Selection(flows) => {
@@ -90,18 +156,79 @@ impl Executable {
                     }
                 }
 
-                return Ok(Next::Null);
+                Err(no_next_node(flows, &cursor).await)
+            }
+            Executable::Inclusive(flows) => {
+                let mut matched = vec![];
+                for flow in flows {
+                    if let Some(flow) = flow.upgrade() {
+                        if flow.check_condition(cursor.clone()).await? {
+                            matched.push(Executable::Flow(Arc::downgrade(&flow)));
+                        }
+                    } else {
+                        return Err(Error::Canceled);
+                    }
+                }
+
+                // always fork, even for a single branch, so the matching join
+                // waits for exactly the branches activated here
+                if matched.is_empty() {
+                    Err(no_next_node(flows, &cursor).await)
+                } else {
+                    Ok(Next::Parallel(matched))
+                }
             }
             Executable::Procedure(procedure) => {
                 if let Some(procedure) = procedure.upgrade() {
-                    procedure.execute(cursor.clone()).await?;
+                    procedure.execute(cursor.clone()).await
                 } else {
-                    return Err(Error::Canceled);
+                    Err(Error::Canceled)
                 }
             }
         }
+    }
+
+    // stable key of the executable, as long as its definition is alive
+    pub fn key(&self) -> Result<ExecutableKey, Error> {
+        let flows = |flows: &Vec<Weak<Flow>>| -> Result<(ProcedureKey, Vec<String>), Error> {
+            let flows = flows
+                .iter()
+                .map(|flow| flow.upgrade().ok_or(Error::Canceled))
+                .collect::<Result<Vec<_>, _>>()?;
+            let procedure = flows
+                .first()
+                .map(|flow| flow.procedure.clone())
+                .ok_or_else(|| Error::InvalidDefinition {
+                    reason: "an empty selection belongs to no procedure".to_string(),
+                })?;
+            let names = flows.iter().map(|flow| flow.name.clone()).collect();
+            Ok((procedure, names))
+        };
+
+        let (procedure, element) = match self {
+            Executable::Node(node) => {
+                let node = node.upgrade().ok_or(Error::Canceled)?;
+                (node.procedure.clone(), Element::Node(node.name.clone()))
+            }
+            Executable::Flow(flow) => {
+                let flow = flow.upgrade().ok_or(Error::Canceled)?;
+                (flow.procedure.clone(), Element::Flow(flow.name.clone()))
+            }
+            Executable::Procedure(procedure) => {
+                let procedure = procedure.upgrade().ok_or(Error::Canceled)?;
+                (procedure.key(), Element::Procedure)
+            }
+            Executable::Selection(selection) => {
+                let (procedure, names) = flows(selection)?;
+                (procedure, Element::Selection(names))
+            }
+            Executable::Inclusive(selection) => {
+                let (procedure, names) = flows(selection)?;
+                (procedure, Element::Inclusive(names))
+            }
+        };
 
-        Ok(Next::Null)
+        Ok(ExecutableKey { procedure, element })
     }
 
     // get outgoings
@@ -116,12 +243,12 @@ impl Executable {
             }
             Executable::Flow(flow) => {
                 if let Some(flow) = flow.upgrade() {
-                    vec![Executable::Node(flow.target_node.clone())]
+                    vec![Executable::Node(flow.target())]
                 } else {
                     vec![]
                 }
             }
-            Executable::Selection(flows) => flows
+            Executable::Selection(flows) | Executable::Inclusive(flows) => flows
                 .iter()
                 .map(|flow| Executable::Flow(flow.clone()))
                 .collect(),
@@ -0,0 +1,204 @@
+use roxmltree::{Document, Node as XmlNode};
+
+use crate::{
+    definition::{FlowDefinition, NodeDefinition, ProcedureDefinition},
+    error::Error,
+    node::NodeKind,
+    timer::parse_duration,
+};
+
+// parse the first process of a BPMN 2.0 document into a procedure definition
+//
+// supported elements: startEvent, endEvent, task, scriptTask, sequenceFlow
+// (with conditionExpression), exclusiveGateway, inclusiveGateway,
+// parallelGateway and intermediateCatchEvent with a timeDuration timer
+pub fn parse(source: &str) -> Result<ProcedureDefinition, Error> {
+    let document = Document::parse(source).map_err(|error| Error::InvalidDefinition {
+        reason: error.to_string(),
+    })?;
+
+    let process = document
+        .descendants()
+        .find(|element| element.has_tag_name("process"))
+        .ok_or_else(|| Error::InvalidDefinition {
+            reason: "no process element".to_string(),
+        })?;
+
+    let mut definition = ProcedureDefinition {
+        name: process
+            .attribute("name")
+            .or(process.attribute("id"))
+            .unwrap_or_default()
+            .to_string(),
+        ..Default::default()
+    };
+
+    // default flows of exclusive gateways are evaluated last
+    let mut default_flows = vec![];
+    // default flows of inclusive gateways with their gateway
+    let mut inclusive_defaults = vec![];
+
+    for element in process.children().filter(XmlNode::is_element) {
+        let tag = element.tag_name().name();
+        if tag == "sequenceFlow" {
+            definition.flows.push(FlowDefinition {
+                name: required(&element, "id")?,
+                source: required(&element, "sourceRef")?,
+                target: required(&element, "targetRef")?,
+                condition: child_text(&element, "conditionExpression"),
+                script: String::new(),
+            });
+            continue;
+        }
+
+        if tag == "startEvent" {
+            definition.start.push(required(&element, "id")?);
+        }
+
+        let kind = match tag {
+            "startEvent" | "endEvent" | "task" | "scriptTask" => NodeKind::Task,
+            "exclusiveGateway" => {
+                if let Some(flow) = element.attribute("default") {
+                    default_flows.push(flow.to_string());
+                }
+                NodeKind::ExclusiveGateway
+            }
+            "inclusiveGateway" => {
+                if let Some(flow) = element.attribute("default") {
+                    inclusive_defaults.push((required(&element, "id")?, flow.to_string()));
+                }
+                NodeKind::InclusiveGateway
+            }
+            "parallelGateway" => NodeKind::ParallelGateway,
+            "intermediateCatchEvent" => timer(&element)?,
+            // documentation and artifacts do not take part in the flow
+            "documentation"
+            | "extensionElements"
+            | "laneSet"
+            | "textAnnotation"
+            | "association"
+            | "dataObject"
+            | "dataObjectReference"
+            | "dataStoreReference"
+            | "ioSpecification"
+            | "property" => continue,
+            _ => {
+                return Err(Error::InvalidDefinition {
+                    reason: format!(
+                        "unsupported element {} {}",
+                        tag,
+                        element.attribute("id").unwrap_or_default()
+                    ),
+                })
+            }
+        };
+
+        definition.nodes.push(NodeDefinition {
+            name: required(&element, "id")?,
+            kind,
+            script: match tag {
+                "scriptTask" => script(&element)?,
+                _ => String::new(),
+            },
+            on_cancel: String::new(),
+        });
+    }
+
+    definition
+        .flows
+        .sort_by_key(|flow| default_flows.contains(&flow.name));
+
+    // the default flow of an inclusive gateway is taken only when no other
+    // flow is, so its condition is the negation of all the others
+    for (gateway, default) in inclusive_defaults {
+        let others: Vec<&FlowDefinition> = definition
+            .flows
+            .iter()
+            .filter(|flow| flow.source == gateway && flow.name != default)
+            .collect();
+        let condition = if others.iter().any(|flow| flow.condition.is_empty()) {
+            "false".to_string()
+        } else {
+            let conditions: Vec<String> = others
+                .iter()
+                .map(|flow| format!("({})", flow.condition))
+                .collect();
+            format!("not ({})", conditions.join(" or "))
+        };
+        if let Some(flow) = definition
+            .flows
+            .iter_mut()
+            .find(|flow| flow.name == default)
+        {
+            flow.condition = condition;
+        }
+    }
+
+    // a parallel or inclusive gateway merging several flows is a join
+    for node in &mut definition.nodes {
+        let incomings = definition
+            .flows
+            .iter()
+            .filter(|flow| flow.target == node.name)
+            .count();
+        let merging = matches!(
+            node.kind,
+            NodeKind::ParallelGateway | NodeKind::InclusiveGateway
+        );
+        if merging && incomings > 1 {
+            node.kind = NodeKind::Join {
+                count: None,
+                merge: Default::default(),
+            };
+        }
+    }
+
+    Ok(definition)
+}
+
+fn required(element: &XmlNode, attribute: &str) -> Result<String, Error> {
+    element
+        .attribute(attribute)
+        .map(str::to_string)
+        .ok_or_else(|| Error::InvalidDefinition {
+            reason: format!(
+                "{} is missing attribute {}",
+                element.tag_name().name(),
+                attribute
+            ),
+        })
+}
+
+fn child_text(element: &XmlNode, name: &str) -> String {
+    element
+        .descendants()
+        .find(|child| child.has_tag_name(name))
+        .and_then(|child| child.text())
+        .map(|text| text.trim().to_string())
+        .unwrap_or_default()
+}
+
+// bpmn scripts know nothing of routing, so the task continues once its
+// script ran unless the script routes by itself
+fn script(element: &XmlNode) -> Result<String, Error> {
+    match element.attribute("scriptFormat") {
+        Some(format) if !format.eq_ignore_ascii_case("lua") => Err(Error::InvalidDefinition {
+            reason: format!("unsupported script format {}", format),
+        }),
+        _ => Ok(format!("set_continue()\n{}", child_text(element, "script"))),
+    }
+}
+
+fn timer(element: &XmlNode) -> Result<NodeKind, Error> {
+    let duration = child_text(element, "timeDuration");
+    if duration.is_empty() {
+        return Err(Error::InvalidDefinition {
+            reason: format!(
+                "intermediateCatchEvent {} has no timeDuration",
+                element.attribute("id").unwrap_or_default()
+            ),
+        });
+    }
+
+    Ok(NodeKind::Timer(parse_duration(&duration)?))
+}
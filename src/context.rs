@@ -1,6 +1,6 @@
 use crate::state::State;
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct Context {
     pub state: State,
 }
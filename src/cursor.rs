@@ -1,8 +1,11 @@
 use std::sync::{Arc, Weak};
 
-use tokio::sync::{
-    mpsc::{channel, Receiver, Sender},
-    RwLock,
+use tokio::{
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        RwLock,
+    },
+    time::Instant,
 };
 use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
@@ -11,10 +14,20 @@ use crate::{
     base::{Executable, Next},
     context::Context,
     error::Error,
+    event::Pending,
     procedure::Procedure,
     scheduler::Scheduler,
+    storage::CursorRecord,
 };
 
+// what happened to a cursor besides moving along, in order
+#[derive(Debug, Clone, PartialEq)]
+pub enum History {
+    Canceled { reason: String },
+    // the cancellation script of the node failed, the cursor is canceled anyway
+    CancelScriptFailed { node: String, reason: String },
+}
+
 pub struct Cursor {
     _weak: Weak<RwLock<Cursor>>,
 
@@ -26,8 +39,16 @@ pub struct Cursor {
     parent: Option<Weak<RwLock<Cursor>>>,
     children: RwLock<Vec<Arc<RwLock<Cursor>>>>,
     is_complete: bool,
+    // when the cursor completed in this process, so a join merges its
+    // children in the order they arrived
+    completed_at: Option<Instant>,
+    history: Vec<History>,
+    // what the cursor waits for, persisted so it survives a restart
+    pending: Option<Pending>,
     cancel: CancellationToken,
-    rx: Receiver<Next>,
+    // taken by the task running the cursor, so waiting for signals does not
+    // keep the cursor locked
+    rx: Option<Receiver<Next>>,
     tx: Sender<Next>,
 }
 
@@ -49,15 +70,18 @@ impl Cursor {
             current: Executable::Procedure(procedure),
             children: RwLock::new(vec![]),
             is_complete: false,
+            completed_at: None,
+            history: vec![],
+            pending: None,
             cancel,
-            rx,
+            rx: Some(rx),
             tx,
         };
 
         Cursor::insert_ptr(Arc::new(RwLock::new(cursor))).await
     }
 
-    pub async fn create_children(&self, executables: &Vec<Executable>) {
+    pub async fn create_children(&self, executables: &Vec<Executable>) -> Vec<Arc<RwLock<Cursor>>> {
         let mut children = vec![];
         for executable in executables {
             let (tx, rx) = channel(100);
@@ -71,14 +95,86 @@ impl Cursor {
                 parent: Some(self._weak.clone()),
                 children: RwLock::new(vec![]),
                 is_complete: false,
+                completed_at: None,
+                history: vec![],
+                pending: None,
                 cancel: self.cancel.child_token(),
-                rx,
+                rx: Some(rx),
                 tx,
             };
             children.push(Cursor::insert_ptr(Arc::new(RwLock::new(child))).await);
         }
 
-        *self.children.write().await = children;
+        *self.children.write().await = children.clone();
+        children
+    }
+
+    // rebuild a persisted cursor, attaching it to its parent
+    pub async fn restore(
+        scheduler: Weak<RwLock<Scheduler>>,
+        procedure: Weak<Procedure>,
+        parent: Option<&Arc<RwLock<Cursor>>>,
+        record: &CursorRecord,
+        current: Executable,
+        pending: Option<Pending>,
+    ) -> Arc<RwLock<Cursor>> {
+        let (tx, rx) = channel(100);
+        let cancel = match parent {
+            Some(parent) => parent.read().await.cancel.child_token(),
+            None => CancellationToken::new(),
+        };
+
+        let cursor = Self {
+            _weak: Weak::new(),
+            id: record.id.clone(),
+            scheduler,
+            context: Context {
+                state: record.state.clone(),
+            },
+            procedure,
+            parent: parent.map(Arc::downgrade),
+            current,
+            children: RwLock::new(vec![]),
+            is_complete: record.is_complete,
+            completed_at: None,
+            history: vec![],
+            pending,
+            cancel,
+            rx: Some(rx),
+            tx,
+        };
+
+        let cursor = Cursor::insert_ptr(Arc::new(RwLock::new(cursor))).await;
+        if let Some(parent) = parent {
+            parent
+                .read()
+                .await
+                .children
+                .write()
+                .await
+                .push(cursor.clone());
+        }
+        cursor
+    }
+
+    // snapshot for storage
+    pub async fn to_record(&self) -> Result<CursorRecord, Error> {
+        let parent = match self.parent()? {
+            Some(parent) => Some(parent.read().await.id().to_string()),
+            None => None,
+        };
+
+        Ok(CursorRecord {
+            id: self.id.clone(),
+            parent,
+            current: self.current.key()?,
+            state: self.context.state.clone(),
+            is_complete: self.is_complete,
+            pending: match &self.pending {
+                Some(pending) => Some(pending.to_record()?),
+                None => None,
+            },
+        })
     }
 
     async fn insert_ptr(cursor: Arc<RwLock<Cursor>>) -> Arc<RwLock<Cursor>> {
@@ -104,6 +200,11 @@ impl Cursor {
         &self.context
     }
 
+    // get context mutably
+    pub fn context_mut(&mut self) -> &mut Context {
+        &mut self.context
+    }
+
     // get procedure
     pub fn procedure(&self) -> &Weak<Procedure> {
         &self.procedure
@@ -119,6 +220,16 @@ impl Cursor {
         self.current = current;
     }
 
+    // get pending
+    pub fn pending(&self) -> Option<&Pending> {
+        self.pending.as_ref()
+    }
+
+    // set pending
+    pub fn set_pending(&mut self, pending: Option<Pending>) {
+        self.pending = pending;
+    }
+
     // get parent
     pub fn parent(&self) -> Result<Option<Arc<RwLock<Cursor>>>, Error> {
         match &self.parent {
@@ -130,13 +241,46 @@ impl Cursor {
         }
     }
 
+    // get children
+    pub async fn children(&self) -> Vec<Arc<RwLock<Cursor>>> {
+        self.children.read().await.clone()
+    }
+
+    // clear children
+    pub async fn clear_children(&self) {
+        self.children.write().await.clear();
+    }
+
+    // has children
+    pub async fn has_children(&self) -> bool {
+        !self.children.read().await.is_empty()
+    }
+
     // is complete
     pub fn is_complete(&self) -> bool {
         self.is_complete
     }
 
+    // get completed at
+    pub fn completed_at(&self) -> Option<Instant> {
+        self.completed_at
+    }
+
+    // get history
+    pub fn history(&self) -> &[History] {
+        &self.history
+    }
+
+    // record in history
+    pub fn record(&mut self, entry: History) {
+        self.history.push(entry);
+    }
+
     // complete
     pub async fn complete(&mut self) {
+        if !self.is_complete {
+            self.completed_at = Some(Instant::now());
+        }
         self.is_complete = true;
         self.cancel.cancel();
     }
@@ -151,8 +295,18 @@ impl Cursor {
         Ok(())
     }
 
-    // signals
-    pub fn signals(&mut self) -> (&Sender<Next>, &mut Receiver<Next>, &CancellationToken) {
-        (&self.tx, &mut self.rx, &self.cancel)
+    // get sender
+    pub fn sender(&self) -> Sender<Next> {
+        self.tx.clone()
+    }
+
+    // take receiver, only the task running the cursor owns it
+    pub fn take_receiver(&mut self) -> Option<Receiver<Next>> {
+        self.rx.take()
+    }
+
+    // get cancellation token
+    pub fn cancellation(&self) -> CancellationToken {
+        self.cancel.clone()
     }
 }
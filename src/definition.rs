@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, node::NodeKind};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProcedureDefinition {
+    pub name: String,
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default)]
+    pub start: Vec<String>,
+    #[serde(default)]
+    pub nodes: Vec<NodeDefinition>,
+    #[serde(default)]
+    pub flows: Vec<FlowDefinition>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NodeDefinition {
+    pub name: String,
+    #[serde(default)]
+    pub kind: NodeKind,
+    #[serde(default)]
+    pub script: String,
+    // run when a cursor is canceled while at the node
+    #[serde(default)]
+    pub on_cancel: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FlowDefinition {
+    pub name: String,
+    pub source: String,
+    pub target: String,
+    #[serde(default)]
+    pub condition: String,
+    #[serde(default)]
+    pub script: String,
+}
+
+impl ProcedureDefinition {
+    // parse from yaml
+    pub fn from_yaml(source: &str) -> Result<Self, Error> {
+        serde_yaml::from_str(source).map_err(|error| Error::InvalidDefinition {
+            reason: error.to_string(),
+        })
+    }
+
+    // parse from json
+    pub fn from_json(source: &str) -> Result<Self, Error> {
+        serde_json::from_str(source).map_err(|error| Error::InvalidDefinition {
+            reason: error.to_string(),
+        })
+    }
+
+    // import from BPMN 2.0 XML
+    pub fn from_bpmn(source: &str) -> Result<Self, Error> {
+        crate::bpmn::parse(source)
+    }
+
+    // serialize to yaml
+    pub fn to_yaml(&self) -> Result<String, Error> {
+        serde_yaml::to_string(self).map_err(|error| Error::InvalidDefinition {
+            reason: error.to_string(),
+        })
+    }
+
+    // serialize to json
+    pub fn to_json(&self) -> Result<String, Error> {
+        serde_json::to_string_pretty(self).map_err(|error| Error::InvalidDefinition {
+            reason: error.to_string(),
+        })
+    }
+}
@@ -1,9 +1,54 @@
 #[derive(Debug)]
 pub enum Error {
     Canceled,
-    NotFound { procedure: String, name: String },
-    NoNextNode { procedure: String, node: String },
-    ScriptFailed { reason: String },
+    NotFound {
+        procedure: String,
+        name: String,
+    },
+    NoNextNode {
+        procedure: String,
+        node: String,
+    },
+    ScriptFailed {
+        reason: String,
+    },
+    InvalidDefinition {
+        reason: String,
+    },
+    DuplicateName {
+        procedure: String,
+        name: String,
+    },
+    DanglingReference {
+        procedure: String,
+        flow: String,
+        node: String,
+    },
+    MissingStart {
+        procedure: String,
+    },
+    MergeConflict {
+        key: String,
+    },
+    InvalidDuration {
+        value: String,
+    },
+    InvalidSchedule {
+        value: String,
+    },
+    CursorNotFound {
+        id: String,
+    },
+    Storage {
+        reason: String,
+    },
+    Encoding {
+        reason: String,
+    },
+    MissingCorrelation {
+        node: String,
+        key: String,
+    },
 }
 
 impl From<mlua::Error> for Error {
@@ -0,0 +1,160 @@
+use std::sync::{Arc, Weak};
+
+use serde::{Deserialize, Serialize};
+use tokio::{sync::RwLock, time::Instant};
+
+use crate::{
+    base::Executable,
+    cursor::Cursor,
+    error::Error,
+    node::{Node, NodeKind},
+    procedure::Procedure,
+    state::{State, Variant},
+    storage::PendingRecord,
+    timer,
+};
+
+// something a waiting cursor can be woken by
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    // a message by name, only delivered to the waits with the same
+    // correlation key unless they wait for any
+    Message {
+        name: String,
+        correlation: Option<String>,
+    },
+    Signal(String),
+    Timer(#[serde(with = "crate::timer::rfc3339")] Instant),
+}
+
+impl Trigger {
+    // whether the delivered trigger fires this awaited one
+    pub fn accepts(&self, delivered: &Trigger) -> bool {
+        match (self, delivered) {
+            (
+                Trigger::Message { name, correlation },
+                Trigger::Message {
+                    name: other,
+                    correlation: key,
+                },
+            ) => name == other && (correlation.is_none() || correlation == key),
+            _ => self == delivered,
+        }
+    }
+}
+
+// the events a cursor waits for, the first one to fire wins
+pub struct Wait {
+    // unique per wait, so stale timers of an earlier wait are ignored
+    pub id: String,
+    pub cursor: Weak<RwLock<Cursor>>,
+    pub events: Vec<(Trigger, Executable)>,
+}
+
+// what a cursor waits for, kept with the cursor so a recovered cursor waits
+// for the same deadlines instead of executing its element again
+#[derive(Clone)]
+pub enum Pending {
+    // parked by `Next::Wait` until the deadline, then continues at the executable
+    Resume(Instant, Executable),
+    // the first of the events to fire wins
+    Events(Vec<(Trigger, Executable)>),
+}
+
+impl Pending {
+    pub fn to_record(&self) -> Result<PendingRecord, Error> {
+        Ok(match self {
+            Pending::Resume(deadline, executable) => PendingRecord::Resume {
+                deadline: *deadline,
+                target: executable.key()?,
+            },
+            Pending::Events(events) => PendingRecord::Events(
+                events
+                    .iter()
+                    .map(|(trigger, executable)| Ok((trigger.clone(), executable.key()?)))
+                    .collect::<Result<_, Error>>()?,
+            ),
+        })
+    }
+
+    pub fn resolve(procedure: &Arc<Procedure>, record: &PendingRecord) -> Result<Self, Error> {
+        Ok(match record {
+            PendingRecord::Resume { deadline, target } => {
+                Pending::Resume(*deadline, procedure.resolve(target)?)
+            }
+            PendingRecord::Events(events) => Pending::Events(
+                events
+                    .iter()
+                    .map(|(trigger, target)| Ok((trigger.clone(), procedure.resolve(target)?)))
+                    .collect::<Result<_, Error>>()?,
+            ),
+        })
+    }
+}
+
+// the event node of an executable, flows stand for the node they lead to
+fn event_node(executable: &Executable) -> Option<Arc<Node>> {
+    let node = match executable {
+        Executable::Node(node) => node.upgrade()?,
+        Executable::Flow(flow) => flow.upgrade()?.target().upgrade()?,
+        _ => return None,
+    };
+
+    match node.kind {
+        NodeKind::Message { .. } | NodeKind::Signal { .. } | NodeKind::Timer(_) => Some(node),
+        _ => None,
+    }
+}
+
+// whether the executables are all events, so a cursor selecting them waits
+pub fn is_events(executables: &[Executable]) -> bool {
+    !executables.is_empty() && executables.iter().all(|e| event_node(e).is_some())
+}
+
+// the trigger of each event, with the event node as the executable to
+// continue from once it fires, messages take their correlation key from state
+pub fn triggers(
+    executables: &[Executable],
+    state: &State,
+) -> Result<Vec<(Trigger, Executable)>, Error> {
+    let mut events = vec![];
+    for executable in executables {
+        let Some(node) = event_node(executable) else {
+            continue;
+        };
+
+        let trigger = match &node.kind {
+            NodeKind::Message { name, correlation } => Trigger::Message {
+                name: name.clone(),
+                correlation: match correlation {
+                    Some(key) => Some(correlation_key(state, key).ok_or_else(|| {
+                        Error::MissingCorrelation {
+                            node: node.name.clone(),
+                            key: key.clone(),
+                        }
+                    })?),
+                    None => None,
+                },
+            },
+            NodeKind::Signal { name } => Trigger::Signal(name.clone()),
+            NodeKind::Timer(duration) => Trigger::Timer(timer::deadline_after(*duration)?),
+            _ => continue,
+        };
+        events.push((trigger, Executable::Node(Arc::downgrade(&node))));
+    }
+
+    Ok(events)
+}
+
+// the correlation key held in the state, only scalars can correlate
+fn correlation_key(state: &State, key: &str) -> Option<String> {
+    match state.get(key)? {
+        Variant::String(value) => Some(value.clone()),
+        Variant::Integer(value) => Some(value.to_string()),
+        Variant::Float(value) => Some(value.to_string()),
+        Variant::Boolean(value) => Some(value.to_string()),
+        Variant::Decimal(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
@@ -0,0 +1,244 @@
+use std::{collections::HashSet, fmt::Write};
+
+use crate::{
+    base::Executable,
+    node::{Node, NodeKind},
+    procedure::Procedure,
+};
+
+// render a procedure as a Graphviz DOT digraph
+// the elements the cursor is currently on are highlighted when given
+pub fn to_dot(procedure: &Procedure, current: Option<&Executable>) -> String {
+    let current = current_names(current);
+    let mut out = String::new();
+
+    writeln!(out, "digraph {} {{", dot_quote(&procedure.name)).unwrap();
+    writeln!(out, "    rankdir=LR;").unwrap();
+
+    for node in sorted_nodes(procedure) {
+        let shape = match node.kind {
+            NodeKind::Task => "box",
+            NodeKind::ExclusiveGateway
+            | NodeKind::InclusiveGateway
+            | NodeKind::ParallelGateway
+            | NodeKind::EventGateway
+            | NodeKind::Join { .. } => "diamond",
+            NodeKind::Timer(_) | NodeKind::Message { .. } | NodeKind::Signal { .. } => "circle",
+        };
+        let mut attributes = vec![
+            format!("label={}", dot_quote(&node_label(node))),
+            format!("shape={}", shape),
+        ];
+        match ending(node) {
+            Some(Ending::Complete) => attributes.push("peripheries=2".to_string()),
+            Some(Ending::Bubble) => attributes.push("style=dashed".to_string()),
+            None => {}
+        }
+        if current.contains(&node.name) {
+            attributes.push("color=red".to_string());
+        }
+        writeln!(
+            out,
+            "    {} [{}];",
+            dot_quote(&node.name),
+            attributes.join(", ")
+        )
+        .unwrap();
+    }
+
+    for (name, source, target, condition) in sorted_flows(procedure) {
+        let mut attributes = vec![];
+        if !condition.is_empty() {
+            attributes.push(format!("label={}", dot_quote(&condition)));
+        }
+        if current.contains(&name) {
+            attributes.push("color=red".to_string());
+        }
+        write!(out, "    {} -> {}", dot_quote(&source), dot_quote(&target)).unwrap();
+        if !attributes.is_empty() {
+            write!(out, " [{}]", attributes.join(", ")).unwrap();
+        }
+        writeln!(out, ";").unwrap();
+    }
+
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+// render a procedure as a Mermaid flowchart
+// the elements the cursor is currently on are highlighted when given
+pub fn to_mermaid(procedure: &Procedure, current: Option<&Executable>) -> String {
+    let current = current_names(current);
+    let nodes = sorted_nodes(procedure);
+    let id = |name: &str| {
+        nodes
+            .iter()
+            .position(|node| node.name == name)
+            .map(|index| format!("n{}", index))
+            .unwrap_or_default()
+    };
+
+    let mut out = String::new();
+    writeln!(out, "flowchart LR").unwrap();
+
+    let mut completes = vec![];
+    let mut bubbles = vec![];
+    let mut currents = vec![];
+    for (index, node) in nodes.iter().enumerate() {
+        let label = mermaid_quote(&node_label(node));
+        let shape = match node.kind {
+            NodeKind::Task => format!("[{}]", label),
+            NodeKind::ExclusiveGateway
+            | NodeKind::InclusiveGateway
+            | NodeKind::ParallelGateway
+            | NodeKind::EventGateway
+            | NodeKind::Join { .. } => format!("{{{}}}", label),
+            NodeKind::Timer(_) | NodeKind::Message { .. } | NodeKind::Signal { .. } => {
+                format!("(({}))", label)
+            }
+        };
+        writeln!(out, "    n{}{}", index, shape).unwrap();
+
+        match ending(node) {
+            Some(Ending::Complete) => completes.push(format!("n{}", index)),
+            Some(Ending::Bubble) => bubbles.push(format!("n{}", index)),
+            None => {}
+        }
+        if current.contains(&node.name) {
+            currents.push(format!("n{}", index));
+        }
+    }
+
+    let mut current_links = vec![];
+    for (index, (name, source, target, condition)) in sorted_flows(procedure).iter().enumerate() {
+        if condition.is_empty() {
+            writeln!(out, "    {} --> {}", id(source), id(target)).unwrap();
+        } else {
+            writeln!(
+                out,
+                "    {} -->|{}| {}",
+                id(source),
+                mermaid_quote(condition),
+                id(target)
+            )
+            .unwrap();
+        }
+        if current.contains(name) {
+            current_links.push(index.to_string());
+        }
+    }
+
+    for (class, style, ids) in [
+        ("complete", "stroke-width:4px", completes),
+        ("bubble", "stroke-dasharray:5 5", bubbles),
+        ("current", "stroke:#f00", currents),
+    ] {
+        if !ids.is_empty() {
+            writeln!(out, "    classDef {} {}", class, style).unwrap();
+            writeln!(out, "    class {} {}", ids.join(","), class).unwrap();
+        }
+    }
+    if !current_links.is_empty() {
+        writeln!(out, "    linkStyle {} stroke:#f00", current_links.join(",")).unwrap();
+    }
+
+    out
+}
+
+enum Ending {
+    Complete,
+    Bubble,
+}
+
+// best effort detection of scripts that end the cursor
+fn ending(node: &Node) -> Option<Ending> {
+    if node.script.contains("set_complete") {
+        Some(Ending::Complete)
+    } else if node.script.contains("set_bubble") {
+        Some(Ending::Bubble)
+    } else {
+        None
+    }
+}
+
+fn node_label(node: &Node) -> String {
+    match &node.kind {
+        NodeKind::Timer(duration) => {
+            format!(
+                "{}\n{}",
+                node.name,
+                crate::timer::format_duration(*duration)
+            )
+        }
+        NodeKind::Message { name, .. } => format!("{}\nmessage {}", node.name, name),
+        NodeKind::Signal { name } => format!("{}\nsignal {}", node.name, name),
+        _ => node.name.clone(),
+    }
+}
+
+fn sorted_nodes(procedure: &Procedure) -> Vec<&Node> {
+    let mut nodes: Vec<&Node> = procedure.nodes.values().map(|node| node.as_ref()).collect();
+    nodes.sort_by(|a, b| a.name.cmp(&b.name));
+    nodes
+}
+
+// (name, source, target, condition) of every flow with both ends alive
+fn sorted_flows(procedure: &Procedure) -> Vec<(String, String, String, String)> {
+    let mut flows: Vec<_> = procedure
+        .flows
+        .values()
+        .filter_map(|flow| {
+            let source = flow.source().upgrade()?;
+            let target = flow.target().upgrade()?;
+            Some((
+                flow.name.clone(),
+                source.name.clone(),
+                target.name.clone(),
+                flow.condition.clone(),
+            ))
+        })
+        .collect();
+    flows.sort();
+    flows
+}
+
+fn current_names(current: Option<&Executable>) -> HashSet<String> {
+    let mut names = HashSet::new();
+    match current {
+        Some(Executable::Node(node)) => {
+            if let Some(node) = node.upgrade() {
+                names.insert(node.name.clone());
+            }
+        }
+        Some(Executable::Flow(flow)) => {
+            if let Some(flow) = flow.upgrade() {
+                names.insert(flow.name.clone());
+            }
+        }
+        Some(Executable::Selection(flows)) | Some(Executable::Inclusive(flows)) => {
+            for flow in flows.iter().filter_map(|flow| flow.upgrade()) {
+                names.insert(flow.name.clone());
+            }
+        }
+        Some(Executable::Procedure(_)) | None => {}
+    }
+    names
+}
+
+fn dot_quote(text: &str) -> String {
+    format!(
+        "\"{}\"",
+        text.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    )
+}
+
+fn mermaid_quote(text: &str) -> String {
+    format!(
+        "\"{}\"",
+        text.replace('"', "#quot;")
+            .replace('\n', "<br/>")
+            .replace('|', "#124;")
+    )
+}
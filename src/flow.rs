@@ -1,27 +1,56 @@
-use std::sync::{Arc, Weak};
+use std::sync::{Arc, OnceLock, Weak};
 
 use tokio::sync::RwLock;
 
-use crate::{base::Next, cursor::Cursor, error::Error, node::Node};
+use crate::{
+    base::{Next, ProcedureKey},
+    cursor::Cursor,
+    error::Error,
+    node::Node,
+    script::Script,
+};
 
 #[derive(Clone, Debug)]
 pub struct Flow {
     pub name: String,
-    pub source_node: Weak<Node>,
-    pub target_node: Weak<Node>,
+    pub procedure: ProcedureKey,
+    // set once the nodes are built, see `Procedure::from_definition`
+    pub source_node: OnceLock<Weak<Node>>,
+    pub target_node: OnceLock<Weak<Node>>,
     pub condition: String,
     pub script: String,
 }
 
 impl Flow {
-    pub async fn check_condition(&self, _: Arc<RwLock<Cursor>>) -> Result<bool, Error> {
-        let result = false;
-        // TODO execute condition script
-        Ok(result)
+    pub fn source(&self) -> Weak<Node> {
+        self.source_node.get().cloned().unwrap_or_default()
     }
 
-    pub async fn execute(&self, _: Arc<RwLock<Cursor>>) -> Result<Next, Error> {
-        // TODO execute script
-        Ok(Next::Continue)
+    pub fn target(&self) -> Weak<Node> {
+        self.target_node.get().cloned().unwrap_or_default()
+    }
+
+    pub async fn check_condition(&self, cursor: Arc<RwLock<Cursor>>) -> Result<bool, Error> {
+        // a flow without condition is always taken
+        if self.condition.trim().is_empty() {
+            return Ok(true);
+        }
+
+        let script = Script::new(cursor);
+        script.evaluate(&self.condition).await
+    }
+
+    pub async fn execute(&self, cursor: Arc<RwLock<Cursor>>) -> Result<Next, Error> {
+        if self.script.is_empty() {
+            return Ok(Next::Continue);
+        }
+
+        // the script is a transition action, so the flow moves on to its
+        // target unless the script routes somewhere else
+        let script = Script::new(cursor);
+        match script.execute_for_next(&self.script).await? {
+            Next::Null => Ok(Next::Continue),
+            next => Ok(next),
+        }
     }
 }
@@ -1,11 +1,20 @@
 pub mod base;
+pub mod bpmn;
 pub mod context;
 pub mod cursor;
+pub mod definition;
 pub mod error;
+pub mod event;
+pub mod export;
 pub mod flow;
+pub mod merge;
 pub mod node;
 pub mod procedure;
 pub mod provider;
 pub mod scheduler;
 pub mod script;
 pub mod state;
+pub mod storage;
+pub mod timer;
+pub mod userdata;
+pub mod validate;
@@ -0,0 +1,94 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::Error,
+    script,
+    state::{State, Variant},
+};
+
+// how the states of joined children are merged back into the parent
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeStrategy {
+    // changes of children that arrived later overwrite earlier ones
+    #[default]
+    LastWriterWins,
+    // children changing the same key to different values is an error
+    FailOnConflict,
+    // every changed key becomes an object with the value of each child that
+    // changed it, by child cursor id, null where the child removed the key
+    Collect,
+    // lua chunk computing the value from `key`, `current` and `values`
+    Script(String),
+}
+
+// merge the children states, by child cursor id in the order they arrived,
+// into the parent state, only keys a child changed compared to the parent
+// are merged
+pub fn merge(
+    strategy: &MergeStrategy,
+    parent: &State,
+    children: &[(String, State)],
+) -> Result<State, Error> {
+    let changed = |child: &State, key: &String| child.get(key) != parent.get(key);
+
+    let keys: BTreeSet<&String> = children
+        .iter()
+        .flat_map(|(_, child)| {
+            child
+                .iter()
+                .map(|(key, _)| key)
+                .chain(parent.iter().map(|(key, _)| key))
+                .filter(|key| changed(child, key))
+        })
+        .collect();
+
+    let mut state = parent.clone();
+    for key in keys {
+        let changes: Vec<(&String, Option<&Variant>)> = children
+            .iter()
+            .filter(|(_, child)| changed(child, key))
+            .map(|(id, child)| (id, child.get(key)))
+            .collect();
+
+        let value = match strategy {
+            MergeStrategy::LastWriterWins => changes.last().and_then(|(_, value)| *value).cloned(),
+            MergeStrategy::FailOnConflict => {
+                if changes.iter().any(|(_, value)| *value != changes[0].1) {
+                    return Err(Error::MergeConflict { key: key.clone() });
+                }
+                changes[0].1.cloned()
+            }
+            MergeStrategy::Collect => Some(Variant::Object(
+                changes
+                    .iter()
+                    .map(|(id, value)| {
+                        let value = value.cloned().unwrap_or(Variant::Null);
+                        (id.to_string(), value)
+                    })
+                    .collect::<HashMap<_, _>>(),
+            )),
+            MergeStrategy::Script(source) => {
+                let values: Vec<Variant> = children
+                    .iter()
+                    .map(|(_, child)| child.get(key).cloned().unwrap_or(Variant::Null))
+                    .collect();
+                match script::merge(source, key, parent.get(key), &values)? {
+                    Variant::Null => None,
+                    value => Some(value),
+                }
+            }
+        };
+
+        match value {
+            Some(value) => state.set(key.clone(), value),
+            None => {
+                state.remove(key);
+            }
+        }
+    }
+
+    Ok(state)
+}
@@ -1,28 +1,128 @@
-use std::sync::Arc;
+use std::{
+    sync::{Arc, Weak},
+    time::Duration,
+};
 
+use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 
 use crate::{
-    base::{Executable, Next},
+    base::{Executable, Next, ProcedureKey},
     cursor::Cursor,
     error::Error,
+    flow::Flow,
+    merge::MergeStrategy,
     script::Script,
+    timer,
 };
 
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeKind {
+    // run the script and follow its decision
+    #[default]
+    Task,
+    // take the first outgoing flow whose condition holds
+    ExclusiveGateway,
+    // take every outgoing flow whose condition holds, each in a child cursor
+    InclusiveGateway,
+    // take all outgoing flows at once
+    ParallelGateway,
+    // wait for whichever event behind the outgoing flows fires first
+    EventGateway,
+    // wait for a message with the name, correlated by the value of a state
+    // key when given
+    Message {
+        name: String,
+        #[serde(default)]
+        correlation: Option<String>,
+    },
+    // wait for a signal with the name
+    Signal {
+        name: String,
+    },
+    // wait for the duration before taking the outgoing flow
+    Timer(#[serde(with = "crate::timer::iso8601")] Duration),
+    // wait for `count` children, or all of them, to arrive before continuing
+    Join {
+        #[serde(default)]
+        count: Option<usize>,
+        #[serde(default)]
+        merge: MergeStrategy,
+    },
+}
+
 #[derive(Debug, Clone)]
 pub struct Node {
     pub name: String,
+    pub procedure: ProcedureKey,
+    pub kind: NodeKind,
     pub script: String,
+    // cleanup run when a cursor is canceled at the node
+    pub on_cancel: String,
     pub incomings: Vec<Executable>,
     pub outgoings: Vec<Executable>,
 }
 
 impl Node {
     pub async fn execute(&self, cursor: Arc<RwLock<Cursor>>) -> Result<Next, Error> {
-        // TODO execute script
-        let script = Script::new(cursor);
-        let next = script.execute_for_next(&self.script).await?;
+        // a child arriving at a join stops there, the parent continues from the
+        // join once enough of its children arrived
+        if let NodeKind::Join { .. } = self.kind {
+            return match cursor.read().await.parent()? {
+                Some(_) => Ok(Next::Complete),
+                None => Ok(Next::Continue),
+            };
+        }
+
+        // a node without script just moves on
+        let next = if self.script.is_empty() {
+            Next::Continue
+        } else {
+            let script = Script::new(cursor.clone());
+            script.execute_for_next(&self.script).await?
+        };
+
+        // gateways and events decide where to continue by themselves
+        match (&self.kind, next) {
+            (NodeKind::Task, next) => Ok(next),
+            (NodeKind::Message { .. } | NodeKind::Signal { .. }, Next::Continue) => {
+                let procedure = cursor.read().await.procedure().upgrade();
+                let procedure = procedure.ok_or(Error::Canceled)?;
+                Ok(Next::Select(vec![procedure.find(&self.name)?]))
+            }
+            (_, Next::Continue) => self.route(),
+            (_, next) => Ok(next),
+        }
+    }
+
+    fn outgoing_flows(&self) -> Vec<Weak<Flow>> {
+        self.outgoings
+            .iter()
+            .filter_map(|outgoing| match outgoing {
+                Executable::Flow(flow) => Some(flow.clone()),
+                _ => None,
+            })
+            .collect()
+    }
 
-        Ok(next)
+    // route according to the node kind
+    fn route(&self) -> Result<Next, Error> {
+        Ok(match &self.kind {
+            NodeKind::Task | NodeKind::Join { .. } => Next::Continue,
+            NodeKind::ExclusiveGateway => Next::One(Executable::Selection(self.outgoing_flows())),
+            NodeKind::InclusiveGateway => Next::One(Executable::Inclusive(self.outgoing_flows())),
+            NodeKind::EventGateway => Next::Select(self.outgoings.clone()),
+            // handled in execute as they wait for themselves
+            NodeKind::Message { .. } | NodeKind::Signal { .. } => Next::Null,
+            NodeKind::ParallelGateway => match self.outgoings.len() {
+                0 | 1 => Next::Continue,
+                _ => Next::Parallel(self.outgoings.clone()),
+            },
+            NodeKind::Timer(duration) => match self.outgoings.first() {
+                Some(outgoing) => Next::Wait(outgoing.clone(), timer::deadline_after(*duration)?),
+                None => Next::Complete,
+            },
+        })
     }
 }
@@ -1,10 +1,14 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, OnceLock},
+};
 
 use tokio::sync::RwLock;
 
 use crate::{
-    base::{Executable, Next},
+    base::{Element, Executable, ExecutableKey, Next, ProcedureKey},
     cursor::Cursor,
+    definition::ProcedureDefinition,
     error::Error,
     flow::Flow,
     node::Node,
@@ -13,6 +17,10 @@ use crate::{
 #[derive(Debug)]
 pub struct Procedure {
     pub name: String,
+    // distinguishes revisions of a definition with the same name
+    pub version: u32,
+    // names of the nodes a new cursor starts at, in parallel if more than one
+    pub start: Vec<String>,
     pub nodes: HashMap<String, Arc<Node>>,
     pub flows: HashMap<String, Arc<Flow>>,
 }
@@ -22,11 +30,111 @@ impl Procedure {
     pub fn new(name: String) -> Self {
         Self {
             name,
+            version: 0,
+            start: vec![],
             nodes: HashMap::new(),
             flows: HashMap::new(),
         }
     }
 
+    // build from definition, resolving flow endpoints by name
+    pub fn from_definition(definition: &ProcedureDefinition) -> Result<Self, Error> {
+        let mut names = HashSet::new();
+        let node_names = definition.nodes.iter().map(|node| &node.name);
+        let flow_names = definition.flows.iter().map(|flow| &flow.name);
+        for name in node_names.chain(flow_names) {
+            if !names.insert(name.as_str()) {
+                return Err(Error::DuplicateName {
+                    procedure: definition.name.clone(),
+                    name: name.clone(),
+                });
+            }
+        }
+
+        let node_index: HashMap<&str, usize> = definition
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| (node.name.as_str(), index))
+            .collect();
+
+        for flow in &definition.flows {
+            for node in [&flow.source, &flow.target] {
+                if !node_index.contains_key(node.as_str()) {
+                    return Err(Error::DanglingReference {
+                        procedure: definition.name.clone(),
+                        flow: flow.name.clone(),
+                        node: node.clone(),
+                    });
+                }
+            }
+        }
+
+        for name in &definition.start {
+            if !node_index.contains_key(name.as_str()) {
+                return Err(Error::NotFound {
+                    procedure: definition.name.clone(),
+                    name: name.clone(),
+                });
+            }
+        }
+
+        let mut builder = Builder {
+            definition,
+            node_index,
+            nodes: Vec::with_capacity(definition.nodes.len()),
+            flows: Vec::with_capacity(definition.flows.len()),
+        };
+        builder.build();
+
+        let mut procedure = Procedure::new(definition.name.clone());
+        procedure.version = definition.version;
+        procedure.start = definition.start.clone();
+        for node in builder.nodes {
+            procedure.nodes.insert(node.name.clone(), node);
+        }
+        for flow in builder.flows {
+            procedure.flows.insert(flow.name.clone(), flow);
+        }
+
+        Ok(procedure)
+    }
+
+    pub fn key(&self) -> ProcedureKey {
+        ProcedureKey {
+            name: self.name.clone(),
+            version: self.version,
+        }
+    }
+
+    // resolve a key taken from an executable of this procedure
+    pub fn resolve(self: &Arc<Self>, key: &ExecutableKey) -> Result<Executable, Error> {
+        let not_found = |name: &str| Error::NotFound {
+            procedure: self.key().to_string(),
+            name: name.to_string(),
+        };
+        if key.procedure != self.key() {
+            return Err(not_found(&key.to_string()));
+        }
+
+        let node = |name: &String| self.nodes.get(name).ok_or_else(|| not_found(name));
+        let flow = |name: &String| self.flows.get(name).ok_or_else(|| not_found(name));
+        let flows = |names: &Vec<String>| {
+            names
+                .iter()
+                .map(|name| flow(name).map(Arc::downgrade))
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(match &key.element {
+            Element::Procedure => Executable::Procedure(Arc::downgrade(self)),
+            Element::Node(name) => Executable::Node(Arc::downgrade(node(name)?)),
+            Element::Flow(name) => Executable::Flow(Arc::downgrade(flow(name)?)),
+            Element::Selection(names) => Executable::Selection(flows(names)?),
+            Element::Inclusive(names) => Executable::Inclusive(flows(names)?),
+        })
+    }
+
     // find executable
     pub fn find(&self, name: &str) -> Result<Executable, Error> {
         let node = self.nodes.get(name);
@@ -45,8 +153,99 @@ impl Procedure {
         })
     }
 
+    // designated start nodes, or the nodes without incomings if none is designated
+    pub fn start_nodes(&self) -> Vec<Arc<Node>> {
+        if !self.start.is_empty() {
+            return self
+                .start
+                .iter()
+                .filter_map(|name| self.nodes.get(name).cloned())
+                .collect();
+        }
+
+        let mut nodes: Vec<Arc<Node>> = self
+            .nodes
+            .values()
+            .filter(|node| node.incomings.is_empty())
+            .cloned()
+            .collect();
+        nodes.sort_by(|a, b| a.name.cmp(&b.name));
+        nodes
+    }
+
     pub async fn execute(&self, _: Arc<RwLock<Cursor>>) -> Result<Next, Error> {
-        // TODO start procedure
-        Ok(Next::Null)
+        let mut starts: Vec<Executable> = self
+            .start_nodes()
+            .iter()
+            .map(|node| Executable::Node(Arc::downgrade(node)))
+            .collect();
+
+        match starts.len() {
+            0 => Err(Error::MissingStart {
+                procedure: self.name.clone(),
+            }),
+            1 => Ok(Next::One(starts.remove(0))),
+            _ => Ok(Next::Parallel(starts)),
+        }
+    }
+}
+
+// nodes and flows point at each other through weak pointers, so the flows
+// are built first and learn their endpoints once all nodes exist, a loop
+// rather than nested `Arc::new_cyclic` calls keeps large graphs off the stack
+struct Builder<'a> {
+    definition: &'a ProcedureDefinition,
+    node_index: HashMap<&'a str, usize>,
+    nodes: Vec<Arc<Node>>,
+    flows: Vec<Arc<Flow>>,
+}
+
+impl Builder<'_> {
+    fn key(&self) -> ProcedureKey {
+        ProcedureKey {
+            name: self.definition.name.clone(),
+            version: self.definition.version,
+        }
+    }
+
+    fn build(&mut self) {
+        for definition in &self.definition.flows {
+            self.flows.push(Arc::new(Flow {
+                name: definition.name.clone(),
+                procedure: self.key(),
+                source_node: OnceLock::new(),
+                target_node: OnceLock::new(),
+                condition: definition.condition.clone(),
+                script: definition.script.clone(),
+            }));
+        }
+
+        let count = self.definition.nodes.len();
+        let mut incomings = vec![vec![]; count];
+        let mut outgoings = vec![vec![]; count];
+        for (flow, definition) in self.flows.iter().zip(&self.definition.flows) {
+            let flow = Executable::Flow(Arc::downgrade(flow));
+            incomings[self.node_index[definition.target.as_str()]].push(flow.clone());
+            outgoings[self.node_index[definition.source.as_str()]].push(flow);
+        }
+
+        let edges = incomings.into_iter().zip(outgoings);
+        for (definition, (incomings, outgoings)) in self.definition.nodes.iter().zip(edges) {
+            self.nodes.push(Arc::new(Node {
+                name: definition.name.clone(),
+                procedure: self.key(),
+                kind: definition.kind.clone(),
+                script: definition.script.clone(),
+                on_cancel: definition.on_cancel.clone(),
+                incomings,
+                outgoings,
+            }));
+        }
+
+        for (flow, definition) in self.flows.iter().zip(&self.definition.flows) {
+            let node = |name: &String| Arc::downgrade(&self.nodes[self.node_index[name.as_str()]]);
+            let _ = flow.source_node.set(node(&definition.source));
+            let _ = flow.target_node.set(node(&definition.target));
+        }
     }
 }
@@ -1,19 +1,72 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, OnceLock, Weak},
+};
 
-use tokio::{select, sync::RwLock};
+use tokio::{select, sync::RwLock, task::JoinHandle, time::Instant};
+use tokio_util::sync::{CancellationToken, DropGuard};
+use uuid::Uuid;
 
 use crate::{
     base::{Executable, Next},
-    cursor::Cursor,
+    cursor::{Cursor, History},
     error::Error,
+    event::{self, Pending, Trigger, Wait},
+    merge,
+    node::{Node, NodeKind},
     procedure::Procedure,
     provider::Provider,
+    script::Script,
+    state::State,
+    storage::{CursorRecord, Storage},
+    timer::Timers,
 };
 
+enum Children {
+    // some children are still running
+    Running,
+    // enough children arrived at a join and the cursor moved past it
+    Joined,
+    // all children are complete without joining
+    Complete,
+}
+
+// what an expired timer does
+pub enum Timeout {
+    // resume a cursor parked by `Next::Wait` at the executable
+    Resume {
+        cursor: Weak<RwLock<Cursor>>,
+        executable: Executable,
+    },
+    // fire the timer event of a cursor's wait
+    Event {
+        wait: String,
+        deadline: Instant,
+    },
+}
+
+type CursorTask = Pin<Box<dyn Future<Output = Result<(), Error>> + Send>>;
+
+#[derive(Default)]
 pub struct Scheduler {
     pub procedures: RwLock<Vec<Arc<Procedure>>>,
     pub cursors: RwLock<Vec<Arc<RwLock<Cursor>>>>,
     pub providers: HashMap<String, Arc<RwLock<Provider>>>,
+    // tasks running the cursors, by cursor id, finished ones are pruned
+    // whenever a cursor is spawned
+    pub handles: RwLock<HashMap<String, JoinHandle<Result<(), Error>>>>,
+    // the first error of a pruned task, reported by `join`
+    failure: Mutex<Option<Error>>,
+    // cursors waiting for events, by cursor id
+    pub waits: RwLock<HashMap<String, Wait>>,
+    // parked cursors and timer events, by cursor id
+    pub timers: Timers<Timeout>,
+    // stops the task draining the timers with the scheduler
+    driver: OnceLock<DropGuard>,
+    // keeps the cursors across restarts
+    pub storage: Option<Arc<dyn Storage>>,
 }
 
 impl Scheduler {
@@ -22,81 +75,745 @@ impl Scheduler {
             procedures: RwLock::new(vec![]),
             cursors: RwLock::new(vec![]),
             providers: HashMap::new(),
+            handles: RwLock::new(HashMap::new()),
+            failure: Mutex::new(None),
+            waits: RwLock::new(HashMap::new()),
+            timers: Timers::new(),
+            driver: OnceLock::new(),
+            storage: None,
         }
     }
 
+    pub fn with_storage(storage: Arc<dyn Storage>) -> Self {
+        Self {
+            storage: Some(storage),
+            ..Self::new()
+        }
+    }
+
+    // start a cursor for each procedure, every cursor runs in its own task
     pub async fn start_procedure(
         scheduler: Arc<RwLock<Self>>,
         procedures: Vec<Arc<Procedure>>,
-    ) -> Result<(), Error> {
-        let s = &mut scheduler.write().await;
+    ) -> Result<Vec<Arc<RwLock<Cursor>>>, Error> {
+        Scheduler::register(&scheduler, &procedures).await;
+
+        let mut cursors = vec![];
         for procedure in procedures {
             let cursor =
                 Cursor::from_procedure(Arc::downgrade(&scheduler), Arc::downgrade(&procedure))
                     .await;
-            s.cursors.write().await.push(cursor.clone());
-            s.loop_run_cursor(cursor).await?;
+            Scheduler::spawn_cursor(&scheduler, cursor.clone()).await?;
+            cursors.push(cursor);
+        }
+
+        Ok(cursors)
+    }
+
+    // rebuild the cursor trees kept in storage and resume the running cursors,
+    // a waiting cursor waits again for the same deadlines and events, the
+    // others continue by executing the element they stood at
+    pub async fn recover(
+        scheduler: Arc<RwLock<Self>>,
+        procedures: Vec<Arc<Procedure>>,
+    ) -> Result<Vec<Arc<RwLock<Cursor>>>, Error> {
+        Scheduler::register(&scheduler, &procedures).await;
+
+        let storage = scheduler.read().await.storage.clone();
+        let records = match storage {
+            Some(storage) => storage.load()?,
+            None => vec![],
+        };
+
+        // children of completed roots are gone with them, completed children
+        // of running parents are kept for their join
+        let mut tree = vec![];
+        let mut pending: Vec<(&CursorRecord, Option<Arc<RwLock<Cursor>>>)> = records
+            .iter()
+            .filter(|record| record.parent.is_none() && !record.is_complete)
+            .map(|record| (record, None))
+            .collect();
+        while let Some((record, parent)) = pending.pop() {
+            let procedure = scheduler
+                .read()
+                .await
+                .procedures
+                .read()
+                .await
+                .iter()
+                .find(|procedure| procedure.key() == record.current.procedure)
+                .cloned()
+                .ok_or_else(|| Error::NotFound {
+                    procedure: record.current.procedure.to_string(),
+                    name: record.id.clone(),
+                })?;
+            let current = procedure.resolve(&record.current)?;
+            let waiting = match &record.pending {
+                Some(waiting) => Some(Pending::resolve(&procedure, waiting)?),
+                None => None,
+            };
+            let cursor = Cursor::restore(
+                Arc::downgrade(&scheduler),
+                Arc::downgrade(&procedure),
+                parent.as_ref(),
+                record,
+                current,
+                waiting,
+            )
+            .await;
+
+            for child in &records {
+                if child.parent.as_deref() == Some(record.id.as_str()) {
+                    pending.push((child, Some(cursor.clone())));
+                }
+            }
+            tree.push(cursor);
+        }
+
+        let mut roots = vec![];
+        for cursor in tree {
+            let (is_complete, is_root) = {
+                let cursor = cursor.read().await;
+                (cursor.is_complete(), cursor.parent()?.is_none())
+            };
+            if !is_complete {
+                Scheduler::spawn_cursor(&scheduler, cursor.clone()).await?;
+            }
+            if is_root {
+                roots.push(cursor);
+            }
+        }
+
+        Ok(roots)
+    }
+
+    // keep the procedures cursors run on, so they can be found by name
+    async fn register(scheduler: &Arc<RwLock<Self>>, procedures: &[Arc<Procedure>]) {
+        let scheduler = scheduler.read().await;
+        let mut registered = scheduler.procedures.write().await;
+        for procedure in procedures {
+            if !registered.iter().any(|other| Arc::ptr_eq(other, procedure)) {
+                registered.push(procedure.clone());
+            }
         }
+    }
+
+    // save the cursor to storage, if any
+    async fn persist(cursor: &Arc<RwLock<Cursor>>) -> Result<(), Error> {
+        let scheduler = cursor.read().await.scheduler()?;
+        let Some(storage) = scheduler.read().await.storage.clone() else {
+            return Ok(());
+        };
+
+        let record = cursor.read().await.to_record().await?;
+        storage.save(&record)
+    }
+
+    // drop cursors from storage, if any
+    async fn forget(
+        scheduler: &Arc<RwLock<Self>>,
+        cursors: &[Arc<RwLock<Cursor>>],
+    ) -> Result<(), Error> {
+        let Some(storage) = scheduler.read().await.storage.clone() else {
+            return Ok(());
+        };
 
+        for cursor in cursors {
+            storage.remove(cursor.read().await.id())?;
+        }
         Ok(())
     }
 
-    async fn loop_run_cursor(&mut self, cursor: Arc<RwLock<Cursor>>) -> Result<(), Error> {
+    // wait until every cursor task, including the ones spawned meanwhile, ends
+    pub async fn join(scheduler: Arc<RwLock<Self>>) -> Result<(), Error> {
+        let mut result = Ok(());
         loop {
-            let mut cursor_ref = cursor.write().await;
-            let (_, rx, cancel) = cursor_ref.signals();
+            let handles: Vec<_> = {
+                let scheduler = scheduler.read().await;
+                let mut handles = scheduler.handles.write().await;
+                handles.drain().map(|(_, handle)| handle).collect()
+            };
+            if handles.is_empty() {
+                let failure = scheduler.read().await.failure.lock().unwrap().take();
+                return match failure {
+                    Some(error) => Err(error),
+                    None => result,
+                };
+            }
 
-            select! {
-                _ = cancel.cancelled() => {
-                    cursor.write().await.complete().await;
+            for handle in handles {
+                let outcome = handle.await.unwrap_or(Err(Error::Canceled));
+                if result.is_ok() {
+                    result = outcome;
+                }
+            }
+        }
+    }
+
+    // fire every cursor waiting for the trigger, writing the payload into
+    // their state, returns how many were woken
+    pub async fn trigger(
+        scheduler: Arc<RwLock<Self>>,
+        trigger: Trigger,
+        payload: State,
+    ) -> Result<usize, Error> {
+        let ids: Vec<String> = {
+            let scheduler = scheduler.read().await;
+            let waits = scheduler.waits.read().await;
+            waits
+                .iter()
+                .filter(|(_, wait)| wait.events.iter().any(|(other, _)| other.accepts(&trigger)))
+                .map(|(id, _)| id.clone())
+                .collect()
+        };
+
+        // a cursor that can not be woken, as its task is gone, does not keep
+        // the others from being woken
+        let mut count = 0;
+        for id in ids {
+            let fired = Scheduler::fire(
+                &scheduler,
+                &id,
+                |_, other| other.accepts(&trigger),
+                &payload,
+            )
+            .await
+            .unwrap_or(false);
+            if fired {
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    // deliver a message to the cursors waiting for it with the correlation
+    // key, returns how many were resumed
+    pub async fn deliver_message(
+        scheduler: Arc<RwLock<Self>>,
+        name: &str,
+        correlation_key: &str,
+        payload: State,
+    ) -> Result<usize, Error> {
+        let trigger = Trigger::Message {
+            name: name.to_string(),
+            correlation: Some(correlation_key.to_string()),
+        };
+        Scheduler::trigger(scheduler, trigger, payload).await
+    }
+
+    // wake every cursor waiting for the signal across all procedures,
+    // returns how many were resumed
+    pub async fn broadcast_signal(
+        scheduler: Arc<RwLock<Self>>,
+        name: &str,
+        payload: State,
+    ) -> Result<usize, Error> {
+        let trigger = Trigger::Signal(name.to_string());
+        Scheduler::trigger(scheduler, trigger, payload).await
+    }
+
+    // cancel a running cursor and its children, each cursor runs the
+    // cancellation script of its node and records the reason
+    pub async fn cancel_cursor(
+        scheduler: Arc<RwLock<Self>>,
+        id: &str,
+        reason: &str,
+    ) -> Result<(), Error> {
+        let cursors = scheduler.read().await.cursors.read().await.clone();
+        let mut found = None;
+        for cursor in cursors {
+            if cursor.read().await.id() == id {
+                found = Some(cursor);
+                break;
+            }
+        }
+        let cursor = found.ok_or_else(|| Error::CursorNotFound { id: id.to_string() })?;
+
+        let mut tree = vec![cursor];
+        let mut index = 0;
+        while index < tree.len() {
+            let children = tree[index].read().await.children().await;
+            tree.extend(children);
+            index += 1;
+        }
+        let mut running = vec![];
+        for cursor in tree {
+            if !cursor.read().await.is_complete() {
+                running.push(cursor);
+            }
+        }
+
+        // children clean up before their parent
+        for cursor in running.iter().rev() {
+            let current = cursor.read().await.current().clone();
+            let Executable::Node(node) = current else {
+                continue;
+            };
+            let Some(node) = node.upgrade().filter(|node| !node.on_cancel.is_empty()) else {
+                continue;
+            };
+            if let Err(error) = Script::new(cursor.clone()).execute(&node.on_cancel).await {
+                cursor.write().await.record(History::CancelScriptFailed {
+                    node: node.name.clone(),
+                    reason: format!("{:?}", error),
+                });
+            }
+        }
+
+        // the parent first, so it does not complete on its own once its
+        // children are gone
+        for cursor in running {
+            let mut cursor = cursor.write().await;
+            cursor.record(History::Canceled {
+                reason: reason.to_string(),
+            });
+            cursor.complete().await;
+        }
+
+        Ok(())
+    }
+
+    // whether the cursor is waiting for events or parked until a deadline
+    pub async fn is_waiting(scheduler: &Arc<RwLock<Self>>, id: &str) -> bool {
+        let scheduler = scheduler.read().await;
+        let waiting = scheduler.waits.read().await.contains_key(id);
+        waiting || scheduler.timers.contains(id)
+    }
+
+    // wait for the first of the events, timers are armed right away
+    async fn register_wait(
+        cursor: Arc<RwLock<Cursor>>,
+        events: Vec<(Trigger, Executable)>,
+    ) -> Result<(), Error> {
+        let (scheduler, id) = {
+            let mut cursor = cursor.write().await;
+            cursor.set_pending(Some(Pending::Events(events.clone())));
+            (cursor.scheduler()?, cursor.id().to_string())
+        };
+
+        let wait = Wait {
+            id: Uuid::now_v7().to_string(),
+            cursor: Arc::downgrade(&cursor),
+            events,
+        };
+        // the timers are armed under the lock, so the cursor is not seen as
+        // waiting before its wait is in place
+        let scheduler = scheduler.read().await;
+        let mut waits = scheduler.waits.write().await;
+        for (trigger, _) in &wait.events {
+            if let Trigger::Timer(deadline) = trigger {
+                let timeout = Timeout::Event {
+                    wait: wait.id.clone(),
+                    deadline: *deadline,
+                };
+                scheduler.timers.insert(&id, *deadline, timeout);
+            }
+        }
+        waits.insert(id, wait);
+
+        Ok(())
+    }
+
+    // fire the first event of the cursor's wait that matches, dropping the
+    // others, and move the cursor past the event
+    async fn fire(
+        scheduler: &Arc<RwLock<Self>>,
+        id: &str,
+        matches: impl Fn(&Wait, &Trigger) -> bool,
+        payload: &State,
+    ) -> Result<bool, Error> {
+        let (wait, executable) = {
+            let scheduler = scheduler.read().await;
+            let mut waits = scheduler.waits.write().await;
+            let Some(wait) = waits.get(id) else {
+                return Ok(false);
+            };
+            let Some((_, executable)) = wait
+                .events
+                .iter()
+                .find(|(trigger, _)| matches(wait, trigger))
+            else {
+                return Ok(false);
+            };
+            let executable = executable.clone();
+            // the timers of the losing events are dropped with the wait
+            scheduler.timers.cancel(id);
+            (waits.remove(id).unwrap(), executable)
+        };
+
+        let Some(cursor) = wait.cursor.upgrade() else {
+            return Ok(false);
+        };
+        let sender = {
+            let mut cursor = cursor.write().await;
+            let state = &mut cursor.context_mut().state;
+            for (key, value) in payload.iter() {
+                state.set(key.clone(), value.clone());
+            }
+            cursor.set_current(executable);
+            cursor.sender()
+        };
+        sender
+            .send(Next::Continue)
+            .await
+            .map_err(|_| Error::Canceled)?;
+
+        Ok(true)
+    }
+
+    // drain the timers in the background until the scheduler is dropped
+    fn drive_timers(scheduler: Weak<RwLock<Self>>, timers: Timers<Timeout>) -> DropGuard {
+        let stop = CancellationToken::new();
+        let stopped = stop.clone();
+        tokio::spawn(async move {
+            loop {
+                let (id, timeout) = select! {
+                    _ = stopped.cancelled() => break,
+                    expired = timers.expired() => expired,
+                };
+                let Some(scheduler) = scheduler.upgrade() else {
                     break;
+                };
+                let _ = Scheduler::expire(&scheduler, &id, timeout).await;
+            }
+        });
+        stop.drop_guard()
+    }
+
+    // handle an expired timer of the cursor
+    async fn expire(
+        scheduler: &Arc<RwLock<Self>>,
+        id: &str,
+        timeout: Timeout,
+    ) -> Result<(), Error> {
+        match timeout {
+            Timeout::Resume { cursor, executable } => {
+                let Some(cursor) = cursor.upgrade() else {
+                    return Ok(());
+                };
+                let sender = cursor.read().await.sender();
+                sender
+                    .send(Next::One(executable))
+                    .await
+                    .map_err(|_| Error::Canceled)
+            }
+            Timeout::Event { wait, deadline } => {
+                let matches = |other: &Wait, trigger: &Trigger| {
+                    other.id == wait && *trigger == Trigger::Timer(deadline)
+                };
+                Scheduler::fire(scheduler, id, matches, &State::new()).await?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn spawn_cursor(
+        scheduler: &Arc<RwLock<Self>>,
+        cursor: Arc<RwLock<Cursor>>,
+    ) -> Result<(), Error> {
+        Scheduler::persist(&cursor).await?;
+
+        let id = cursor.read().await.id().to_string();
+        let weak = Arc::downgrade(scheduler);
+        let scheduler = scheduler.read().await;
+        scheduler.cursors.write().await.push(cursor.clone());
+        scheduler
+            .driver
+            .get_or_init(|| Scheduler::drive_timers(weak, scheduler.timers.clone()));
+
+        let finished: Vec<_> = {
+            let mut handles = scheduler.handles.write().await;
+            let ids: Vec<String> = handles
+                .iter()
+                .filter(|(_, handle)| handle.is_finished())
+                .map(|(id, _)| id.clone())
+                .collect();
+            let handle = tokio::spawn(Scheduler::run_cursor(cursor));
+            handles.insert(id, handle);
+            ids.iter().filter_map(|id| handles.remove(id)).collect()
+        };
+        for handle in finished {
+            if let Err(error) = handle.await.unwrap_or(Err(Error::Canceled)) {
+                scheduler.failure.lock().unwrap().get_or_insert(error);
+            }
+        }
+        Ok(())
+    }
+
+    // boxed to break the cycle between spawning children and running a cursor
+    fn run_cursor(cursor: Arc<RwLock<Cursor>>) -> CursorTask {
+        Box::pin(async move {
+            let result = Scheduler::loop_run_cursor(cursor.clone()).await;
+            if result.is_err() {
+                cursor.write().await.complete().await;
+            }
+
+            let (scheduler, parent, id) = {
+                let cursor = cursor.read().await;
+                (
+                    cursor.scheduler().ok(),
+                    cursor.parent().ok().flatten(),
+                    cursor.id().to_string(),
+                )
+            };
+
+            // completed cursors leave the scheduler
+            if let Some(scheduler) = &scheduler {
+                let scheduler = scheduler.read().await;
+                scheduler.waits.write().await.remove(&id);
+                scheduler.timers.cancel(&id);
+                scheduler
+                    .cursors
+                    .write()
+                    .await
+                    .retain(|other| !Arc::ptr_eq(other, &cursor));
+            }
+
+            // wake the parent so it can check on its children, unless it
+            // already moved on without this cursor, which is then forgotten
+            let storage = match &scheduler {
+                Some(scheduler) => scheduler.read().await.storage.clone(),
+                None => None,
+            };
+            let record = cursor.read().await.to_record().await;
+            if let Some(parent) = parent {
+                // the parent is kept locked, so it can not forget this cursor
+                // between the check and the save
+                let (sender, saved) = {
+                    let parent = parent.read().await;
+                    let children = parent.children().await;
+                    let listed = children.iter().any(|child| Arc::ptr_eq(child, &cursor));
+                    let saved = match (&storage, listed) {
+                        (Some(storage), true) => record.and_then(|record| storage.save(&record)),
+                        (Some(storage), false) => storage.remove(&id),
+                        (None, _) => Ok(()),
+                    };
+                    (listed.then(|| parent.sender()), saved)
+                };
+                if let Some(sender) = sender {
+                    let _ = sender.send(Next::Null).await;
                 }
-                next = rx.recv() => {
-                    if let Some(next) = next {
-                        self.handle_next_operation(cursor.clone(), next).await?;
-                    }
-                    let next = self.execute_current(cursor.clone()).await?;
-                    self.handle_next_operation(cursor.clone(), next).await?;
-                    if cursor.read().await.is_complete() {
+                result.and(saved)
+            } else {
+                match &storage {
+                    Some(storage) => result.and(record.and_then(|record| storage.save(&record))),
+                    None => result,
+                }
+            }
+        })
+    }
+
+    async fn loop_run_cursor(cursor: Arc<RwLock<Cursor>>) -> Result<(), Error> {
+        let (rx, cancel) = {
+            let mut cursor = cursor.write().await;
+            (cursor.take_receiver(), cursor.cancellation())
+        };
+        let Some(mut rx) = rx else {
+            // the cursor is already running in another task
+            return Ok(());
+        };
+
+        // a recovered cursor waits again instead of executing its element,
+        // armed here so nothing fires before the cursor listens
+        let pending = cursor.read().await.pending().cloned();
+        let mut restored = false;
+        if let Some(pending) = pending {
+            Scheduler::rearm(cursor.clone(), pending).await?;
+            restored = true;
+        }
+
+        loop {
+            if cursor.read().await.is_complete() {
+                break;
+            }
+
+            // a forked cursor waits until all its children are complete
+            let children = cursor.read().await.children().await;
+            let idle = if std::mem::take(&mut restored) {
+                true
+            } else if children.is_empty() {
+                let next = Scheduler::execute_current(cursor.clone()).await?;
+                let idle = match &next {
+                    Next::Null | Next::Wait(..) => true,
+                    Next::Select(executables) => event::is_events(executables),
+                    _ => false,
+                };
+                Scheduler::handle_next_operation(cursor.clone(), next).await?;
+                Scheduler::persist(&cursor).await?;
+                idle
+            } else {
+                match Scheduler::handle_children(cursor.clone(), &children).await? {
+                    Children::Running => true,
+                    Children::Joined => false,
+                    Children::Complete => {
+                        cursor.write().await.complete().await;
                         break;
                     }
                 }
+            };
+
+            if !idle || cursor.read().await.is_complete() {
+                continue;
+            }
+
+            // nothing to do until the cursor is signaled, a cursor waiting
+            // for events ignores anything but the event firing
+            let id = cursor.read().await.id().to_string();
+            let scheduler = cursor.read().await.scheduler()?;
+            let next = loop {
+                let next = select! {
+                    _ = cancel.cancelled() => None,
+                    next = rx.recv() => next,
+                };
+                if next != Some(Next::Null) || !Scheduler::is_waiting(&scheduler, &id).await {
+                    break next;
+                }
+            };
+
+            match next {
+                Some(next) => {
+                    // the wait is over, fired or resumed
+                    cursor.write().await.set_pending(None);
+                    Scheduler::handle_next_operation(cursor.clone(), next).await?;
+                    Scheduler::persist(&cursor).await?;
+                }
+                None => {
+                    cursor.write().await.complete().await;
+                    break;
+                }
             }
         }
         Ok(())
     }
 
+    // wait again for what a recovered cursor waited for before the restart
+    async fn rearm(cursor: Arc<RwLock<Cursor>>, pending: Pending) -> Result<(), Error> {
+        match pending {
+            Pending::Resume(time, executable) => {
+                Scheduler::handle_next_operation(cursor, Next::Wait(executable, time)).await
+            }
+            Pending::Events(events) => Scheduler::register_wait(cursor, events).await,
+        }
+    }
+
     // execute with cursor
-    async fn execute_current(&mut self, cursor: Arc<RwLock<Cursor>>) -> Result<Next, Error> {
+    async fn execute_current(cursor: Arc<RwLock<Cursor>>) -> Result<Next, Error> {
         if cursor.read().await.is_complete() {
             return Ok(Next::Complete);
         }
 
-        cursor.read().await.current().execute(cursor.clone()).await
+        // release the cursor before executing so scripts can update it
+        let current = cursor.read().await.current().clone();
+        current.execute(cursor.clone()).await
+    }
+
+    // join the children once enough of them arrived at the same join node
+    async fn handle_children(
+        cursor: Arc<RwLock<Cursor>>,
+        children: &[Arc<RwLock<Cursor>>],
+    ) -> Result<Children, Error> {
+        let mut complete = true;
+        // join nodes with the indices of the children that arrived there
+        let mut arrivals: Vec<(Arc<Node>, Vec<usize>)> = vec![];
+        for (index, child) in children.iter().enumerate() {
+            let child_ref = child.read().await;
+            if !child_ref.is_complete() {
+                complete = false;
+                continue;
+            }
+
+            let Executable::Node(node) = child_ref.current() else {
+                continue;
+            };
+            let Some(node) = node.upgrade() else {
+                continue;
+            };
+            if !matches!(node.kind, NodeKind::Join { .. }) {
+                continue;
+            }
+
+            match arrivals
+                .iter_mut()
+                .find(|(join, _)| Arc::ptr_eq(join, &node))
+            {
+                Some((_, arrived)) => arrived.push(index),
+                None => arrivals.push((node, vec![index])),
+            }
+        }
+
+        for (join, arrived) in arrivals {
+            let NodeKind::Join { count, merge } = &join.kind else {
+                continue;
+            };
+            if arrived.len() < count.unwrap_or(children.len()).min(children.len()) {
+                continue;
+            }
+
+            // the children that did not make it are no longer needed
+            for (index, child) in children.iter().enumerate() {
+                if !arrived.contains(&index) {
+                    child.write().await.complete().await;
+                }
+            }
+
+            // in the order the children arrived, the ones completed before a
+            // restart first
+            let mut ordered = vec![];
+            for index in arrived {
+                let child = children[index].read().await;
+                let state = (child.id().to_string(), child.context().state.clone());
+                ordered.push((child.completed_at(), state));
+            }
+            ordered.sort_by_key(|(completed_at, _)| *completed_at);
+            let states: Vec<_> = ordered.into_iter().map(|(_, state)| state).collect();
+            let state = merge::merge(merge, &cursor.read().await.context().state, &states)?;
+
+            {
+                let mut cursor = cursor.write().await;
+                cursor.clear_children().await;
+                cursor.context_mut().state = state;
+                cursor.set_current(Executable::Node(Arc::downgrade(&join)));
+            }
+            let scheduler = cursor.read().await.scheduler()?;
+            Scheduler::forget(&scheduler, children).await?;
+            Scheduler::handle_next_operation(cursor.clone(), Next::Continue).await?;
+
+            return Ok(Children::Joined);
+        }
+
+        if complete {
+            Ok(Children::Complete)
+        } else {
+            Ok(Children::Running)
+        }
     }
 
     // handle parallel operation
     async fn handle_parallel(
-        &mut self,
         cursor: Arc<RwLock<Cursor>>,
         executables: &Vec<Executable>,
     ) -> Result<(), Error> {
-        cursor.write().await.create_children(executables).await;
+        let (scheduler, children) = {
+            let cursor = cursor.read().await;
+            (
+                cursor.scheduler()?,
+                cursor.create_children(executables).await,
+            )
+        };
+
+        for child in children {
+            Scheduler::spawn_cursor(&scheduler, child).await?;
+        }
 
         Ok(())
     }
 
-    async fn handle_next_operation(
-        &mut self,
-        cursor: Arc<RwLock<Cursor>>,
-        next: Next,
-    ) -> Result<(), Error> {
+    async fn handle_next_operation(cursor: Arc<RwLock<Cursor>>, next: Next) -> Result<(), Error> {
         match next {
             Next::Null => Ok(()),
             Next::Continue => {
-                let current_cursor = cursor.read().await;
-                let current = current_cursor.current();
-                let outgoings = &current.outgoings();
+                let outgoings = cursor.read().await.current().outgoings();
                 match outgoings.len() {
                     0 => {
                         cursor.write().await.complete().await;
@@ -108,7 +825,7 @@ impl Scheduler {
                             .set_current(outgoings.first().unwrap().clone());
                     }
                     _ => {
-                        self.handle_parallel(cursor.clone(), outgoings).await?;
+                        Scheduler::handle_parallel(cursor.clone(), &outgoings).await?;
                     }
                 }
                 Ok(())
@@ -118,15 +835,26 @@ impl Scheduler {
                 Ok(())
             }
             Next::Parallel(executables) => {
-                self.handle_parallel(cursor.clone(), &executables).await?;
+                // nothing to fork, like a node without outgoings
+                if executables.is_empty() {
+                    cursor.write().await.complete().await;
+                } else {
+                    Scheduler::handle_parallel(cursor.clone(), &executables).await?;
+                }
                 Ok(())
             }
             Next::Select(ref executables) => {
+                if event::is_events(executables) {
+                    let state = cursor.read().await.context().state.clone();
+                    let events = event::triggers(executables, &state)?;
+                    return Scheduler::register_wait(cursor.clone(), events).await;
+                }
+
                 let mut flows = vec![];
                 for executable in executables {
                     match executable {
                         Executable::Node(node) => {
-                            if let Some(_) = node.upgrade() {
+                            if node.upgrade().is_some() {
                                 cursor.write().await.set_current(executable.clone());
                                 return Ok(());
                             } else {
@@ -136,13 +864,13 @@ impl Scheduler {
                         Executable::Flow(flow) => {
                             flows.push(flow.clone());
                         }
-                        Executable::Selection(selection) => {
+                        Executable::Selection(selection) | Executable::Inclusive(selection) => {
                             for flow in selection {
                                 flows.push(flow.clone());
                             }
                         }
                         Executable::Procedure(procedure) => {
-                            if let Some(_) = procedure.upgrade() {
+                            if procedure.upgrade().is_some() {
                                 cursor.write().await.set_current(executable.clone());
                                 return Ok(());
                             } else {
@@ -173,9 +901,17 @@ impl Scheduler {
                 Ok(())
             }
             Next::Wait(executable, time) => {
-                // delay to time
-                tokio::time::sleep_until(time).await;
-                cursor.write().await.set_current(executable);
+                // park the cursor, it is resumed by the timers at the time
+                let (scheduler, id) = {
+                    let mut cursor = cursor.write().await;
+                    cursor.set_pending(Some(Pending::Resume(time, executable.clone())));
+                    (cursor.scheduler()?, cursor.id().to_string())
+                };
+                let timeout = Timeout::Resume {
+                    cursor: Arc::downgrade(&cursor),
+                    executable,
+                };
+                scheduler.read().await.timers.insert(&id, time, timeout);
                 Ok(())
             }
             Next::Complete => {
@@ -183,7 +919,15 @@ impl Scheduler {
                 Ok(())
             }
             Next::Bubble => {
-                cursor.write().await.complete_and_bubble().await?;
+                // release the cursor before locking the parent
+                let parent = {
+                    let mut cursor = cursor.write().await;
+                    cursor.complete().await;
+                    cursor.parent()?
+                };
+                if let Some(parent) = parent {
+                    parent.write().await.complete().await;
+                }
                 Ok(())
             }
         }
@@ -1,145 +1,453 @@
-use std::{cell::RefCell, collections::HashMap, sync::Arc};
+use std::{cell::RefCell, collections::HashMap, ffi::c_void, sync::Arc, time::Duration};
 
-use mlua::Lua;
+use mlua::{FromLua, IntoLua, Lua};
 use tokio::sync::RwLock;
 
 use crate::{
-    base::Next,
+    base::{Executable, Next},
     cursor::Cursor,
     error::Error,
+    procedure::Procedure,
     state::{State, Variant},
+    timer,
+    userdata::{self, LuaVariant},
 };
 
 pub struct Script {
     cursor: Arc<RwLock<Cursor>>,
-    lua: Lua,
 }
 
 impl Script {
     pub fn new(cursor: Arc<RwLock<Cursor>>) -> Script {
-        let script = Script {
-            cursor,
-            lua: Lua::new(),
+        Script { cursor }
+    }
+
+    // execute for side effects only, discarding the routing decision
+    pub async fn execute(&self, script: &str) -> Result<(), Error> {
+        self.execute_for_next(script).await?;
+        Ok(())
+    }
+
+    // evaluate a boolean expression with read access to the cursor state
+    pub async fn evaluate(&self, expression: &str) -> Result<bool, Error> {
+        let state = self.cursor.read().await.context().state.clone();
+        evaluate(&state, expression)
+    }
+
+    pub async fn execute_for_next(&self, script: &str) -> Result<Next, Error> {
+        let (procedure, state) = {
+            let cursor = self.cursor.read().await;
+            let procedure = cursor.procedure().upgrade().ok_or(Error::Canceled)?;
+            (procedure, cursor.context().state.clone())
         };
 
-        script
+        // scripts work on a copy of the state which is committed on success
+        let (next, state) = run(&procedure, state, script)?;
+        self.cursor.write().await.context_mut().state = state;
+
+        Ok(next)
+    }
+}
+
+// the lua state is neither `Send` nor `Sync`, so it only lives inside these
+// synchronous functions and never across an await point
+fn evaluate(state: &State, expression: &str) -> Result<bool, Error> {
+    let lua = Lua::new();
+    userdata::register(&lua)?;
+    expose_state(&lua, state)?;
+
+    let value: mlua::Value = lua.load(format!("return ({})", expression)).eval()?;
+    match value {
+        mlua::Value::Boolean(result) => Ok(result),
+        value => Err(Error::ScriptFailed {
+            reason: format!(
+                "condition `{}` returned {} instead of boolean",
+                expression,
+                value.type_name()
+            ),
+        }),
     }
+}
+
+// compute a merged value with a lua chunk, see `MergeStrategy::Script`
+pub fn merge(
+    source: &str,
+    key: &str,
+    current: Option<&Variant>,
+    values: &[Variant],
+) -> Result<Variant, Error> {
+    let lua = Lua::new();
+    userdata::register(&lua)?;
+    let globals = lua.globals();
+    globals.set("key", key)?;
+    globals.set(
+        "current",
+        match current {
+            Some(current) => current.into_lua(&lua)?,
+            None => mlua::Value::Nil,
+        },
+    )?;
+    globals.set("values", Variant::Array(values.to_vec()))?;
+
+    Ok(lua.load(source).eval::<Variant>()?)
+}
+
+fn run(procedure: &Procedure, state: State, script: &str) -> Result<(Next, State), Error> {
+    let lua = &Lua::new();
+    userdata::register(lua)?;
+    let next = RefCell::new(Next::Null);
+    let state = RefCell::new(state);
+
+    lua.scope(|scope| {
+        let globals = lua.globals();
+
+        globals.set(
+            "get_state",
+            scope.create_function(|lua, key: String| match state.borrow().get(&key) {
+                Some(value) => value.into_lua(lua),
+                None => Ok(mlua::Value::Nil),
+            })?,
+        )?;
+
+        globals.set(
+            "set_state",
+            scope.create_function(|_, (key, value): (String, Variant)| {
+                state.borrow_mut().set(key, value);
+                Ok(())
+            })?,
+        )?;
+
+        globals.set(
+            "has_state",
+            scope.create_function(|_, key: String| Ok(state.borrow().has(&key)))?,
+        )?;
+
+        globals.set(
+            "remove_state",
+            scope.create_function(|lua, key: String| match state.borrow_mut().remove(&key) {
+                Some(value) => value.into_lua(lua),
+                None => Ok(mlua::Value::Nil),
+            })?,
+        )?;
+
+        globals.set(
+            "set_continue",
+            scope.create_function_mut(|_, ()| {
+                next.replace(Next::Continue);
+                Ok(())
+            })?,
+        )?;
+
+        globals.set(
+            "set_one",
+            scope.create_function_mut(|_, name: String| {
+                next.replace(Next::One(find(procedure, &name)?));
+                Ok(())
+            })?,
+        )?;
+
+        globals.set(
+            "set_parallel",
+            scope.create_function_mut(|_, names: Vec<String>| {
+                let executables = names
+                    .iter()
+                    .map(|name| find(procedure, name))
+                    .collect::<mlua::Result<_>>()?;
+                next.replace(Next::Parallel(executables));
+                Ok(())
+            })?,
+        )?;
+
+        globals.set(
+            "set_select",
+            scope.create_function_mut(|_, names: Vec<String>| {
+                let executables = names
+                    .iter()
+                    .map(|name| find(procedure, name))
+                    .collect::<mlua::Result<_>>()?;
+                next.replace(Next::Select(executables));
+                Ok(())
+            })?,
+        )?;
+
+        // wait a number of seconds, an ISO-8601 duration or a `duration()` before
+        // continuing
+        globals.set(
+            "set_wait",
+            scope.create_function_mut(|_, (name, duration): (String, mlua::Value)| {
+                let duration = match duration {
+                    mlua::Value::Integer(seconds) => {
+                        Duration::try_from_secs_f64(seconds as f64).ok()
+                    }
+                    mlua::Value::Number(seconds) => Duration::try_from_secs_f64(seconds).ok(),
+                    mlua::Value::String(text) => timer::parse_duration(text.to_str()?).ok(),
+                    mlua::Value::UserData(userdata) => match &userdata.borrow::<LuaVariant>()?.0 {
+                        Variant::Duration(delta) => delta.to_std().ok(),
+                        _ => None,
+                    },
+                    _ => None,
+                }
+                .ok_or_else(|| mlua::Error::external("invalid duration"))?;
+                let deadline = timer::deadline_after(duration)
+                    .map_err(|_| mlua::Error::external("duration out of range"))?;
+                next.replace(Next::Wait(find(procedure, &name)?, deadline));
+                Ok(())
+            })?,
+        )?;
+
+        // wait until an RFC 3339 timestamp before continuing
+        globals.set(
+            "set_wait_until",
+            scope.create_function_mut(|_, (name, timestamp): (String, String)| {
+                let time = timer::parse_timestamp(&timestamp)
+                    .map_err(|_| mlua::Error::external("invalid timestamp"))?;
+                next.replace(Next::Wait(find(procedure, &name)?, time));
+                Ok(())
+            })?,
+        )?;
+
+        // wait for the next match of a cron expression before continuing
+        globals.set(
+            "set_wait_cron",
+            scope.create_function_mut(|_, (name, expression): (String, String)| {
+                let time = timer::next_cron(&expression)
+                    .map_err(|_| mlua::Error::external("invalid cron expression"))?;
+                next.replace(Next::Wait(find(procedure, &name)?, time));
+                Ok(())
+            })?,
+        )?;
+
+        globals.set(
+            "set_complete",
+            scope.create_function_mut(|_, ()| {
+                next.replace(Next::Complete);
+                Ok(())
+            })?,
+        )?;
+
+        globals.set(
+            "set_bubble",
+            scope.create_function_mut(|_, ()| {
+                next.replace(Next::Bubble);
+                Ok(())
+            })?,
+        )?;
 
-    pub fn execute(&self, script: &String) -> Result<State, Error> {
-        let lua = &self.lua;
-        let mut state = State::new();
+        lua.load(script).exec()?;
 
-        lua.scope(|scope| {
-            lua.globals().set(
-                "set_state",
-                scope.create_function_mut(|_, (key, value): (String, Variant)| {
-                    state.set(key, value);
-                    Ok(())
-                })?,
-            )?;
+        Ok(())
+    })?;
 
-            lua.load(script).exec()?;
+    Ok((next.into_inner(), state.into_inner()))
+}
+
+// resolve a routing target by name
+fn find(procedure: &Procedure, name: &str) -> mlua::Result<Executable> {
+    procedure
+        .find(name)
+        .map_err(|_| mlua::Error::external("not found"))
+}
 
-            Ok(())
-        })?;
+const STATE_KEY: &str = "donut.state";
 
-        Ok(state)
+// expose a snapshot of the state as the `state` table, through `get_state` and
+// `has_state`, and as fallback for undefined globals
+fn expose_state(lua: &Lua, state: &State) -> mlua::Result<()> {
+    let table = lua.create_table()?;
+    for (key, value) in state.iter() {
+        table.set(key.as_str(), value)?;
     }
 
-    pub async fn execute_for_next(&self, script: &String) -> Result<Next, Error> {
-        let lua = &self.lua;
-        let cursor = &self.cursor.read().await;
-        let procedure = cursor.procedure().upgrade().unwrap();
+    lua.set_named_registry_value(STATE_KEY, table.clone())?;
 
-        let next = RefCell::new(Next::Null);
+    let globals = lua.globals();
+    globals.set(
+        "get_state",
+        lua.create_function(|lua, key: String| {
+            lua.named_registry_value::<mlua::Table>(STATE_KEY)?
+                .get::<_, mlua::Value>(key)
+        })?,
+    )?;
+    globals.set(
+        "has_state",
+        lua.create_function(|lua, key: String| {
+            lua.named_registry_value::<mlua::Table>(STATE_KEY)?
+                .contains_key(key)
+        })?,
+    )?;
+    globals.set("state", table.clone())?;
 
-        lua.scope(|scope| {
-            let globals = lua.globals();
+    let metatable = lua.create_table()?;
+    metatable.set("__index", table)?;
+    globals.set_metatable(Some(metatable));
 
-            globals.set(
-                "set_continue",
-                scope.create_function_mut(|_, ()| {
-                    next.replace(Next::Continue);
-                    Ok(())
-                })?,
-            )?;
+    Ok(())
+}
 
-            globals.set(
-                "set_one",
-                scope.create_function_mut(|_, name: String| {
-                    next.replace(Next::One(
-                        procedure
-                            .find(&name)
-                            .map_err(|_| mlua::Error::external("not found"))?,
-                    ));
-                    Ok(())
-                })?,
-            )?;
+const OBJECT_KEY: &str = "donut.object";
 
-            globals.set(
-                "set_complete",
-                scope.create_function_mut(|_, ()| {
-                    next.replace(Next::Complete);
-                    Ok(())
-                })?,
-            )?;
+// objects carry a shared marker metatable, so an empty object does not come
+// back as an empty array
+fn object_metatable(lua: &Lua) -> mlua::Result<mlua::Table<'_>> {
+    if let Ok(metatable) = lua.named_registry_value::<mlua::Table>(OBJECT_KEY) {
+        return Ok(metatable);
+    }
+    let metatable = lua.create_table()?;
+    lua.set_named_registry_value(OBJECT_KEY, metatable.clone())?;
+    Ok(metatable)
+}
 
-            globals.set(
-                "set_bubble",
-                scope.create_function_mut(|_, ()| {
-                    next.replace(Next::Bubble);
-                    Ok(())
-                })?,
-            )?;
+impl<'lua> IntoLua<'lua> for &Variant {
+    fn into_lua(self, lua: &'lua Lua) -> mlua::Result<mlua::Value<'lua>> {
+        match self {
+            Variant::Null => Ok(mlua::Value::Nil),
+            Variant::String(s) => Ok(mlua::Value::String(lua.create_string(s)?)),
+            Variant::Integer(i) => Ok(mlua::Value::Integer(*i)),
+            Variant::Float(n) => Ok(mlua::Value::Number(*n)),
+            Variant::Boolean(b) => Ok(mlua::Value::Boolean(*b)),
+            Variant::Array(array) => {
+                let table = lua.create_table_with_capacity(array.len(), 0)?;
+                for (index, value) in array.iter().enumerate() {
+                    table.raw_set(index + 1, value)?;
+                }
+                Ok(mlua::Value::Table(table))
+            }
+            Variant::Object(object) => {
+                let table = lua.create_table_with_capacity(0, object.len())?;
+                for (key, value) in object {
+                    table.raw_set(key.as_str(), value)?;
+                }
+                table.set_metatable(Some(object_metatable(lua)?));
+                Ok(mlua::Value::Table(table))
+            }
+            variant => Ok(mlua::Value::UserData(
+                lua.create_userdata(LuaVariant(variant.clone()))?,
+            )),
+        }
+    }
+}
 
-            lua.load(script).exec()?;
+impl<'lua> IntoLua<'lua> for Variant {
+    fn into_lua(self, lua: &'lua Lua) -> mlua::Result<mlua::Value<'lua>> {
+        (&self).into_lua(lua)
+    }
+}
 
-            Ok(())
-        })?;
+impl<'lua> FromLua<'lua> for Variant {
+    fn from_lua(value: mlua::Value<'lua>, lua: &'lua Lua) -> mlua::Result<Self> {
+        from_lua(value, lua, &mut vec![])
+    }
+}
 
-        Ok(next.into_inner())
+// `ancestors` holds the tables being converted, to reject cycles, a table
+// shared by two branches is fine and converted twice
+fn from_lua<'lua>(
+    value: mlua::Value<'lua>,
+    lua: &'lua Lua,
+    ancestors: &mut Vec<*const c_void>,
+) -> mlua::Result<Variant> {
+    match value {
+        mlua::Value::Nil => Ok(Variant::Null),
+        mlua::Value::String(s) => Ok(Variant::String(s.to_str()?.to_string())),
+        mlua::Value::Integer(i) => Ok(Variant::Integer(i)),
+        mlua::Value::Number(n) => Ok(Variant::Float(n)),
+        mlua::Value::Boolean(b) => Ok(Variant::Boolean(b)),
+        mlua::Value::Table(table) => {
+            let pointer = table.to_pointer();
+            if ancestors.contains(&pointer) {
+                return Err(conversion_error(
+                    "table",
+                    "table contains a reference cycle",
+                ));
+            }
+            ancestors.push(pointer);
+            let result = table_to_variant(table, lua, ancestors);
+            ancestors.pop();
+            result
+        }
+        mlua::Value::LightUserData(_) => Ok(Variant::Null),
+        mlua::Value::Function(_) => Ok(Variant::Null),
+        mlua::Value::Thread(_) => Ok(Variant::Null),
+        mlua::Value::UserData(userdata) => match userdata.borrow::<LuaVariant>() {
+            Ok(variant) => Ok(variant.0.clone()),
+            Err(_) => Ok(Variant::Null),
+        },
+        mlua::Value::Error(_) => Ok(Variant::Null),
     }
 }
 
-impl mlua::FromLua<'_> for Variant {
-    fn from_lua(value: mlua::Value<'_>, lua: &'_ mlua::Lua) -> mlua::Result<Self> {
-        match value {
-            mlua::Value::Nil => Ok(Variant::Null),
-            mlua::Value::String(s) => Ok(Variant::String(s.to_str()?.to_string())),
-            mlua::Value::Integer(i) => Ok(Variant::Integer(i)),
-            mlua::Value::Number(n) => Ok(Variant::Float(n)),
-            mlua::Value::Boolean(b) => Ok(Variant::Boolean(b)),
-            mlua::Value::Table(t) => {
-                let mut array: Vec<Variant> = vec![];
-                let mut object: HashMap<String, Variant> = HashMap::new();
-                t.for_each::<mlua::Value, mlua::Value>(|key, value| {
-                    if key.is_string() {
-                        if let Some(key) = key.as_string() {
-                            object
-                                .insert(key.to_str()?.to_string(), Variant::from_lua(value, lua)?);
-                        }
-                        return Ok(());
-                    }
+// a table with only positive integer keys that fill at least half of its
+// length is an array, holes become nulls, anything else is an object with
+// its number and boolean keys turned into strings
+fn table_to_variant<'lua>(
+    table: mlua::Table<'lua>,
+    lua: &'lua Lua,
+    ancestors: &mut Vec<*const c_void>,
+) -> mlua::Result<Variant> {
+    let mut entries = vec![];
+    for pair in table.clone().pairs::<mlua::Value, mlua::Value>() {
+        let (key, value) = pair?;
+        entries.push((key, from_lua(value, lua, ancestors)?));
+    }
 
-                    if key.is_integer() {
-                        array.push(Variant::from_lua(value, lua)?);
-                        return Ok(());
-                    }
+    if entries.is_empty() {
+        let is_object = match table.get_metatable() {
+            Some(metatable) => metatable.equals(&object_metatable(lua)?)?,
+            None => false,
+        };
+        return Ok(if is_object {
+            Variant::Object(HashMap::new())
+        } else {
+            Variant::Array(vec![])
+        });
+    }
 
-                    Ok(())
-                })?;
+    let indices: Option<Vec<usize>> = entries
+        .iter()
+        .map(|(key, _)| match key {
+            mlua::Value::Integer(i) if *i >= 1 => usize::try_from(*i).ok(),
+            _ => None,
+        })
+        .collect();
+    if let Some(indices) = indices {
+        let length = indices.iter().copied().max().unwrap_or(0);
+        if length <= entries.len() * 2 {
+            let mut array = vec![Variant::Null; length];
+            for (index, (_, value)) in indices.into_iter().zip(entries) {
+                array[index - 1] = value;
+            }
+            return Ok(Variant::Array(array));
+        }
+    }
 
-                if object.is_empty() {
-                    Ok(Variant::Array(array))
-                } else {
-                    Ok(Variant::Object(object))
-                }
+    let mut object = HashMap::with_capacity(entries.len());
+    for (key, value) in entries {
+        let key = match key {
+            mlua::Value::String(s) => s.to_str()?.to_string(),
+            mlua::Value::Integer(i) => i.to_string(),
+            mlua::Value::Number(n) => n.to_string(),
+            mlua::Value::Boolean(b) => b.to_string(),
+            key => {
+                return Err(conversion_error(
+                    key.type_name(),
+                    "table keys must be strings, numbers or booleans",
+                ))
             }
-            mlua::Value::LightUserData(_) => Ok(Variant::Null),
-            mlua::Value::Function(_) => Ok(Variant::Null),
-            mlua::Value::Thread(_) => Ok(Variant::Null),
-            mlua::Value::UserData(_) => Ok(Variant::Null),
-            mlua::Value::Error(_) => Ok(Variant::Null),
+        };
+        if object.insert(key.clone(), value).is_some() {
+            return Err(conversion_error(
+                "table",
+                &format!("key `{}` appears more than once", key),
+            ));
         }
     }
+    Ok(Variant::Object(object))
+}
+
+fn conversion_error(from: &'static str, message: &str) -> mlua::Error {
+    mlua::Error::FromLuaConversionError {
+        from,
+        to: "Variant",
+        message: Some(message.to_string()),
+    }
 }
@@ -1,11 +1,29 @@
-use std::collections::HashMap;
+use std::{borrow::Cow, collections::HashMap, fmt};
 
-#[derive(Clone)]
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, SecondsFormat, TimeDelta, Utc};
+use rust_decimal::Decimal;
+use serde::{
+    de::{self, MapAccess, SeqAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::{Number, Value};
+
+use crate::{error::Error, timer};
+
+// serialized as a plain map of its entries
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct State {
     value: HashMap<String, Variant>,
 }
 
-#[derive(Clone)]
+// serialized as the matching plain value, integers and floats stay apart
+// as long as the format tells them apart, which json and msgpack do
+// the other scalars become a tagged string such as `{"$decimal": "12.50"}`,
+// bytes are native in binary formats, keys of objects that start with `$`
+// get another one, so no object reads back as a tag
+#[derive(Debug, Clone, PartialEq)]
 pub enum Variant {
     Null,
     String(String),
@@ -14,6 +32,10 @@ pub enum Variant {
     Boolean(bool),
     Array(Vec<Variant>),
     Object(HashMap<String, Variant>),
+    DateTime(DateTime<Utc>),
+    Duration(TimeDelta),
+    Decimal(Decimal),
+    Bytes(Vec<u8>),
 }
 
 impl State {
@@ -43,4 +65,303 @@ impl State {
     pub fn has(&self, key: &str) -> bool {
         self.value.contains_key(key)
     }
+
+    // iterate over all entries
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Variant)> {
+        self.value.iter()
+    }
+}
+
+impl State {
+    // encode as msgpack
+    pub fn to_msgpack(&self) -> Result<Vec<u8>, Error> {
+        rmp_serde::to_vec(self).map_err(encoding_error)
+    }
+
+    // decode from msgpack
+    pub fn from_msgpack(bytes: &[u8]) -> Result<Self, Error> {
+        rmp_serde::from_slice(bytes).map_err(encoding_error)
+    }
+}
+
+impl Variant {
+    // encode as msgpack
+    pub fn to_msgpack(&self) -> Result<Vec<u8>, Error> {
+        rmp_serde::to_vec(self).map_err(encoding_error)
+    }
+
+    // decode from msgpack
+    pub fn from_msgpack(bytes: &[u8]) -> Result<Self, Error> {
+        rmp_serde::from_slice(bytes).map_err(encoding_error)
+    }
+}
+
+const DATETIME_TAG: &str = "$datetime";
+const DURATION_TAG: &str = "$duration";
+const DECIMAL_TAG: &str = "$decimal";
+const BYTES_TAG: &str = "$bytes";
+
+impl Variant {
+    // the tag and text a value is encoded with, plain values have none
+    fn tagged(&self) -> Option<(&'static str, String)> {
+        match self {
+            Variant::DateTime(value) => Some((
+                DATETIME_TAG,
+                value.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            )),
+            Variant::Duration(value) => Some((DURATION_TAG, timer::format_delta(*value))),
+            Variant::Decimal(value) => Some((DECIMAL_TAG, value.to_string())),
+            Variant::Bytes(value) => Some((BYTES_TAG, BASE64.encode(value))),
+            _ => None,
+        }
+    }
+
+    // the inverse of `tagged`, a tag with a text it cannot read stays a
+    // plain object
+    fn untagged(tag: &str, text: &str) -> Option<Variant> {
+        match tag {
+            DATETIME_TAG => DateTime::parse_from_rfc3339(text)
+                .ok()
+                .map(|value| Variant::DateTime(value.with_timezone(&Utc))),
+            DURATION_TAG => timer::parse_delta(text).ok().map(Variant::Duration),
+            DECIMAL_TAG => text.parse().ok().map(Variant::Decimal),
+            BYTES_TAG => BASE64.decode(text).ok().map(Variant::Bytes),
+            _ => None,
+        }
+    }
+
+    // an object, unless it is a single tag entry, with the keys as they
+    // were encoded
+    fn from_object(values: HashMap<String, Variant>) -> Variant {
+        if values.len() == 1 {
+            if let Some((tag, Variant::String(text))) = values.iter().next() {
+                if let Some(variant) = Variant::untagged(tag, text) {
+                    return variant;
+                }
+            }
+        }
+        Variant::Object(unescape_keys(values))
+    }
+}
+
+fn escape_key(key: &str) -> Cow<'_, str> {
+    if key.starts_with('$') {
+        Cow::Owned(format!("${}", key))
+    } else {
+        Cow::Borrowed(key)
+    }
+}
+
+fn unescape_keys<T>(values: HashMap<String, T>) -> HashMap<String, T> {
+    values
+        .into_iter()
+        .map(|(key, value)| match key.strip_prefix("$$") {
+            Some(rest) => (format!("${}", rest), value),
+            None => (key, value),
+        })
+        .collect()
+}
+
+fn encoding_error(error: impl fmt::Display) -> Error {
+    Error::Encoding {
+        reason: error.to_string(),
+    }
+}
+
+impl Serialize for State {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_object(&self.value, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for State {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(State {
+            value: unescape_keys(HashMap::deserialize(deserializer)?),
+        })
+    }
+}
+
+// sorted by key, so equal objects encode the same
+fn serialize_object<S: Serializer>(
+    values: &HashMap<String, Variant>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut entries: Vec<_> = values.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    let mut map = serializer.serialize_map(Some(entries.len()))?;
+    for (key, value) in entries {
+        map.serialize_entry(&escape_key(key), value)?;
+    }
+    map.end()
+}
+
+impl Serialize for Variant {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Variant::Null => serializer.serialize_unit(),
+            Variant::String(value) => serializer.serialize_str(value),
+            Variant::Integer(value) => serializer.serialize_i64(*value),
+            Variant::Float(value) => serializer.serialize_f64(*value),
+            Variant::Boolean(value) => serializer.serialize_bool(*value),
+            Variant::Array(values) => values.serialize(serializer),
+            Variant::Object(values) => serialize_object(values, serializer),
+            Variant::Bytes(values) if !serializer.is_human_readable() => {
+                serializer.serialize_bytes(values)
+            }
+            variant => {
+                let (tag, text) = variant.tagged().expect("plain values are handled above");
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry(tag, &text)?;
+                map.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Variant {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(VariantVisitor)
+    }
+}
+
+struct VariantVisitor;
+
+impl<'de> Visitor<'de> for VariantVisitor {
+    type Value = Variant;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a null, string, number, boolean, array or map")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Variant, E> {
+        Ok(Variant::Null)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Variant, E> {
+        Ok(Variant::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Variant, D::Error> {
+        Variant::deserialize(deserializer)
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<Variant, E> {
+        Ok(Variant::Boolean(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Variant, E> {
+        Ok(Variant::Integer(value))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Variant, E> {
+        i64::try_from(value)
+            .map(Variant::Integer)
+            .map_err(|_| E::custom(format!("integer {} is out of range", value)))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Variant, E> {
+        Ok(Variant::Float(value))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Variant, E> {
+        Ok(Variant::String(value.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Variant, E> {
+        Ok(Variant::String(value))
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Variant, E> {
+        Ok(Variant::Bytes(value.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, value: Vec<u8>) -> Result<Variant, E> {
+        Ok(Variant::Bytes(value))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Variant, A::Error> {
+        let mut values = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+        while let Some(value) = seq.next_element()? {
+            values.push(value);
+        }
+        Ok(Variant::Array(values))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Variant, A::Error> {
+        let mut values = HashMap::with_capacity(map.size_hint().unwrap_or_default());
+        while let Some((key, value)) = map.next_entry()? {
+            values.insert(key, value);
+        }
+        Ok(Variant::from_object(values))
+    }
+}
+
+impl From<Value> for Variant {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Null => Variant::Null,
+            Value::Bool(value) => Variant::Boolean(value),
+            Value::Number(value) => match value.as_i64() {
+                Some(value) => Variant::Integer(value),
+                None => Variant::Float(value.as_f64().unwrap_or(f64::NAN)),
+            },
+            Value::String(value) => Variant::String(value),
+            Value::Array(values) => Variant::Array(values.into_iter().map(Variant::from).collect()),
+            Value::Object(values) => Variant::from_object(
+                values
+                    .into_iter()
+                    .map(|(key, value)| (key, Variant::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+// json has no room for non-finite floats, they become null
+impl From<Variant> for Value {
+    fn from(variant: Variant) -> Self {
+        match variant {
+            Variant::Null => Value::Null,
+            Variant::String(value) => Value::String(value),
+            Variant::Integer(value) => Value::Number(value.into()),
+            Variant::Float(value) => Number::from_f64(value).map_or(Value::Null, Value::Number),
+            Variant::Boolean(value) => Value::Bool(value),
+            Variant::Array(values) => Value::Array(values.into_iter().map(Value::from).collect()),
+            Variant::Object(values) => Value::Object(
+                values
+                    .into_iter()
+                    .map(|(key, value)| (escape_key(&key).into_owned(), Value::from(value)))
+                    .collect(),
+            ),
+            variant => {
+                let (tag, text) = variant.tagged().expect("plain values are handled above");
+                Value::Object(
+                    [(tag.to_string(), Value::String(text))]
+                        .into_iter()
+                        .collect(),
+                )
+            }
+        }
+    }
+}
+
+// only a json object makes a state
+impl TryFrom<Value> for State {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self, Error> {
+        match Variant::from(value) {
+            Variant::Object(value) => Ok(State { value }),
+            _ => Err(Error::Encoding {
+                reason: "state must be a json object".to_string(),
+            }),
+        }
+    }
+}
+
+impl From<State> for Value {
+    fn from(state: State) -> Self {
+        Value::from(Variant::Object(state.value))
+    }
 }
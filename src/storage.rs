@@ -0,0 +1,286 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use tokio::time::Instant;
+
+use crate::{base::ExecutableKey, error::Error, event::Trigger, state::State};
+
+// everything needed to rebuild a cursor after a restart
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CursorRecord {
+    pub id: String,
+    pub parent: Option<String>,
+    // the element the cursor stands at, which also names its procedure
+    pub current: ExecutableKey,
+    pub state: State,
+    pub is_complete: bool,
+    // what the cursor waits for at `current`, if anything
+    #[serde(default)]
+    pub pending: Option<PendingRecord>,
+}
+
+// a pending wait with its deadlines as timestamps
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PendingRecord {
+    Resume {
+        #[serde(with = "crate::timer::rfc3339")]
+        deadline: Instant,
+        target: ExecutableKey,
+    },
+    Events(Vec<(Trigger, ExecutableKey)>),
+}
+
+// persists cursors, the latest record of a cursor replaces the earlier ones
+pub trait Storage: Send + Sync {
+    fn save(&self, record: &CursorRecord) -> Result<(), Error>;
+    fn remove(&self, id: &str) -> Result<(), Error>;
+    fn load(&self) -> Result<Vec<CursorRecord>, Error>;
+}
+
+fn storage_error(error: impl std::fmt::Display) -> Error {
+    Error::Storage {
+        reason: error.to_string(),
+    }
+}
+
+// keeps the records in memory, for tests and embedding
+#[derive(Default)]
+pub struct MemoryStorage {
+    records: Mutex<HashMap<String, CursorRecord>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn save(&self, record: &CursorRecord) -> Result<(), Error> {
+        let mut records = self.records.lock().unwrap();
+        records.insert(record.id.clone(), record.clone());
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> Result<(), Error> {
+        self.records.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    fn load(&self) -> Result<Vec<CursorRecord>, Error> {
+        Ok(self.records.lock().unwrap().values().cloned().collect())
+    }
+}
+
+// one entry per line of the append-only file
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Entry {
+    Save(Box<CursorRecord>),
+    Remove(String),
+}
+
+// appends every change as a json line, replaying the file on load
+pub struct FileStorage {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl FileStorage {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(storage_error)?;
+
+        // drop a line a crash cut short, the next append would continue it
+        let content = std::fs::read(&path).map_err(storage_error)?;
+        if !content.is_empty() && !content.ends_with(b"\n") {
+            let end = content.iter().rposition(|&byte| byte == b'\n');
+            file.set_len(end.map_or(0, |end| end as u64 + 1))
+                .map_err(storage_error)?;
+        }
+
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    fn append(&self, entry: &Entry) -> Result<(), Error> {
+        let mut line = serde_json::to_string(entry).map_err(storage_error)?;
+        line.push('\n');
+
+        let mut file = self.file.lock().unwrap();
+        file.write_all(line.as_bytes()).map_err(storage_error)?;
+        file.sync_data().map_err(storage_error)
+    }
+}
+
+impl Storage for FileStorage {
+    fn save(&self, record: &CursorRecord) -> Result<(), Error> {
+        self.append(&Entry::Save(Box::new(record.clone())))
+    }
+
+    fn remove(&self, id: &str) -> Result<(), Error> {
+        self.append(&Entry::Remove(id.to_string()))
+    }
+
+    fn load(&self) -> Result<Vec<CursorRecord>, Error> {
+        let _guard = self.file.lock().unwrap();
+        let file = File::open(&self.path).map_err(storage_error)?;
+
+        let mut order = vec![];
+        let mut records = HashMap::new();
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(storage_error)?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let entry = serde_json::from_str::<Entry>(&line)
+                .map_err(|error| storage_error(format!("line {}: {}", index + 1, error)))?;
+            match entry {
+                Entry::Save(record) => {
+                    if !records.contains_key(&record.id) {
+                        order.push(record.id.clone());
+                    }
+                    records.insert(record.id.clone(), *record);
+                }
+                Entry::Remove(id) => {
+                    records.remove(&id);
+                }
+            }
+        }
+
+        Ok(order
+            .into_iter()
+            .filter_map(|id| records.remove(&id))
+            .collect())
+    }
+}
+
+// keeps one row per cursor in a sqlite database
+pub struct SqliteStorage {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_connection(Connection::open(path).map_err(storage_error)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, Error> {
+        Self::from_connection(Connection::open_in_memory().map_err(storage_error)?)
+    }
+
+    fn from_connection(connection: Connection) -> Result<Self, Error> {
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS cursors (
+                    id TEXT PRIMARY KEY,
+                    parent TEXT,
+                    current TEXT NOT NULL,
+                    state TEXT NOT NULL,
+                    is_complete INTEGER NOT NULL,
+                    pending TEXT
+                )",
+            )
+            .map_err(storage_error)?;
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn save(&self, record: &CursorRecord) -> Result<(), Error> {
+        let current = serde_json::to_string(&record.current).map_err(storage_error)?;
+        let state = serde_json::to_string(&record.state).map_err(storage_error)?;
+        let pending = match &record.pending {
+            Some(pending) => Some(serde_json::to_string(pending).map_err(storage_error)?),
+            None => None,
+        };
+
+        self.connection
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT OR REPLACE INTO cursors
+                    (id, parent, current, state, is_complete, pending)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    record.id,
+                    record.parent,
+                    current,
+                    state,
+                    record.is_complete,
+                    pending
+                ],
+            )
+            .map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> Result<(), Error> {
+        self.connection
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM cursors WHERE id = ?1", params![id])
+            .map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn load(&self) -> Result<Vec<CursorRecord>, Error> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare(
+                "SELECT id, parent, current, state, is_complete, pending
+                    FROM cursors ORDER BY rowid",
+            )
+            .map_err(storage_error)?;
+
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, bool>(4)?,
+                    row.get::<_, Option<String>>(5)?,
+                ))
+            })
+            .map_err(storage_error)?;
+
+        let mut records = vec![];
+        for row in rows {
+            let (id, parent, current, state, is_complete, pending) = row.map_err(storage_error)?;
+            records.push(CursorRecord {
+                id,
+                parent,
+                current: serde_json::from_str(&current).map_err(storage_error)?,
+                state: serde_json::from_str(&state).map_err(storage_error)?,
+                is_complete,
+                pending: match pending {
+                    Some(pending) => Some(serde_json::from_str(&pending).map_err(storage_error)?),
+                    None => None,
+                },
+            });
+        }
+
+        Ok(records)
+    }
+}
@@ -0,0 +1,389 @@
+use std::{
+    collections::HashMap,
+    future::poll_fn,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    task::Poll,
+    time::Duration,
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use cron::Schedule;
+use tokio::time::Instant;
+use tokio_util::time::{delay_queue::Key, DelayQueue};
+
+use crate::error::Error;
+
+// parse an ISO-8601 duration such as `PT5M`, `P1DT12H` or `PT0.5S`
+// years and months have no fixed length and are rejected
+pub fn parse_duration(text: &str) -> Result<Duration, Error> {
+    let invalid = || Error::InvalidDuration {
+        value: text.to_string(),
+    };
+
+    let rest = text.trim().strip_prefix('P').ok_or_else(invalid)?;
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+
+    // counted in nanoseconds, so formatted durations parse back exactly
+    let mut nanos = 0u128;
+    let mut in_time = false;
+    // `PT` or `P1DT` without a component after the designator are invalid
+    let mut components = 0;
+    let mut number = String::new();
+    for c in rest.chars() {
+        match c {
+            'T' if !in_time && number.is_empty() => {
+                in_time = true;
+                components = 0;
+            }
+            '0'..='9' | '.' | ',' => number.push(if c == ',' { '.' } else { c }),
+            _ => {
+                let value = parse_nanos(&number).ok_or_else(invalid)?;
+                number.clear();
+                components += 1;
+                let unit: u128 = match (in_time, c) {
+                    (false, 'W') => 7 * 86400,
+                    (false, 'D') => 86400,
+                    (true, 'H') => 3600,
+                    (true, 'M') => 60,
+                    (true, 'S') => 1,
+                    _ => return Err(invalid()),
+                };
+                nanos = value
+                    .checked_mul(unit)
+                    .and_then(|value| nanos.checked_add(value))
+                    .ok_or_else(invalid)?;
+            }
+        }
+    }
+
+    if !number.is_empty() || components == 0 {
+        return Err(invalid());
+    }
+
+    let seconds = u64::try_from(nanos / NANOS).map_err(|_| invalid())?;
+    Ok(Duration::new(seconds, (nanos % NANOS) as u32))
+}
+
+const NANOS: u128 = 1_000_000_000;
+
+// a decimal number of units as nanoseconds of a unit, digits beyond the
+// nanosecond are cut off
+fn parse_nanos(number: &str) -> Option<u128> {
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if whole.is_empty() && fraction.is_empty() || fraction.contains('.') {
+        return None;
+    }
+
+    let whole: u128 = if whole.is_empty() {
+        0
+    } else {
+        whole.parse().ok()?
+    };
+    let fraction = format!("{:0<9}", &fraction[..fraction.len().min(9)]);
+    whole
+        .checked_mul(NANOS)?
+        .checked_add(fraction.parse().ok()?)
+}
+
+// format a duration as ISO-8601, the inverse of `parse_duration`
+pub fn format_duration(duration: Duration) -> String {
+    match duration.subsec_nanos() {
+        0 => format!("PT{}S", duration.as_secs()),
+        nanos => {
+            let fraction = format!("{:09}", nanos);
+            format!(
+                "PT{}.{}S",
+                duration.as_secs(),
+                fraction.trim_end_matches('0')
+            )
+        }
+    }
+}
+
+// a signed duration such as `-PT5M`, as a difference of timestamps can be
+pub fn parse_delta(text: &str) -> Result<TimeDelta, Error> {
+    let text = text.trim();
+    let (negative, rest) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+
+    let delta = TimeDelta::from_std(parse_duration(rest)?).map_err(|_| Error::InvalidDuration {
+        value: text.to_string(),
+    })?;
+    Ok(if negative { -delta } else { delta })
+}
+
+// format a signed duration, the inverse of `parse_delta`
+pub fn format_delta(delta: TimeDelta) -> String {
+    let text = format_duration(delta.abs().to_std().unwrap_or_default());
+    if delta < TimeDelta::zero() {
+        format!("-{}", text)
+    } else {
+        text
+    }
+}
+
+// the instant an RFC 3339 timestamp such as `2030-01-01T09:00:00Z` is reached,
+// timestamps in the past are due right away
+pub fn parse_timestamp(text: &str) -> Result<Instant, Error> {
+    let time = DateTime::parse_from_rfc3339(text.trim()).map_err(|_| Error::InvalidSchedule {
+        value: text.to_string(),
+    })?;
+    instant_at(time.with_timezone(&Utc)).ok_or_else(|| Error::InvalidSchedule {
+        value: text.to_string(),
+    })
+}
+
+// the instant the duration from now is over
+pub fn deadline_after(duration: Duration) -> Result<Instant, Error> {
+    Instant::now()
+        .checked_add(duration)
+        .ok_or_else(|| Error::InvalidDuration {
+            value: format_duration(duration),
+        })
+}
+
+// the next instant matching a cron expression, in UTC
+// the seconds field is optional, `0 9 * * Mon` fires at 09:00:00 on mondays
+// five fields are a unix expression, with sunday as 0 or 7, six or seven
+// fields follow the cron crate, with sunday as 1
+pub fn next_cron(expression: &str) -> Result<Instant, Error> {
+    let invalid = || Error::InvalidSchedule {
+        value: expression.to_string(),
+    };
+
+    let fields: Vec<&str> = expression.split_whitespace().collect();
+    let schedule = match fields.as_slice() {
+        [minute, hour, day, month, weekday] => {
+            let weekday = unix_weekdays(weekday).ok_or_else(invalid)?;
+            Schedule::from_str(&format!(
+                "0 {} {} {} {} {}",
+                minute, hour, day, month, weekday
+            ))
+        }
+        _ => Schedule::from_str(expression.trim()),
+    }
+    .map_err(|_| invalid())?;
+
+    let time = schedule.upcoming(Utc).next().ok_or_else(invalid)?;
+    instant_at(time).ok_or_else(invalid)
+}
+
+// renumber a unix day of week field, 0-7 with 0 and 7 for sunday, to the
+// 1-7 of the cron crate, names and wildcards stay as they are
+fn unix_weekdays(field: &str) -> Option<String> {
+    let number = |text: &str| text.parse::<u8>().ok().filter(|day| *day <= 7);
+    let day = |text: &str| -> Option<String> {
+        match text.parse::<u8>() {
+            Ok(day @ 0..=6) => Some((day + 1).to_string()),
+            Ok(7) => Some("1".to_string()),
+            Ok(_) => None,
+            Err(_) => Some(text.to_string()),
+        }
+    };
+
+    let mut parts = vec![];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(step)),
+            None => (part, None),
+        };
+        // numeric bounds, a start with a step runs until sunday as 7
+        let bounds = match range.split_once('-') {
+            Some((first, last)) => number(first).zip(number(last)),
+            None if step.is_some() => number(range).map(|first| (first, 7)),
+            None => None,
+        };
+        match bounds {
+            Some((0, 7)) if step.is_none() => parts.push("*".to_string()),
+            // sunday as 7 wraps around to 1 in the crate, and so may the
+            // steps, so these are listed day by day
+            Some((first, last)) if step.is_some() || last == 7 => {
+                let step = match step {
+                    Some(step) => step.parse::<usize>().ok().filter(|step| *step > 0)?,
+                    None => 1,
+                };
+                for unix in (first..=last).step_by(step) {
+                    parts.push(day(&unix.to_string())?);
+                }
+            }
+            _ => {
+                let range = match range.split_once('-') {
+                    Some((first, last)) => format!("{}-{}", day(first)?, day(last)?),
+                    None => day(range)?,
+                };
+                parts.push(match step {
+                    Some(step) => format!("{}/{}", range, step),
+                    None => range,
+                });
+            }
+        }
+    }
+    parts.dedup();
+    Some(parts.join(","))
+}
+
+fn instant_at(time: DateTime<Utc>) -> Option<Instant> {
+    let delay = (time - Utc::now()).to_std().unwrap_or_default();
+    Instant::now().checked_add(delay)
+}
+
+// the wall clock time an instant is reached, the inverse of `instant_at`
+fn time_at(instant: Instant) -> DateTime<Utc> {
+    let now = Instant::now();
+    let time = match instant.checked_duration_since(now) {
+        Some(delay) => TimeDelta::from_std(delay)
+            .ok()
+            .and_then(|delay| Utc::now().checked_add_signed(delay)),
+        None => TimeDelta::from_std(now - instant)
+            .ok()
+            .and_then(|elapsed| Utc::now().checked_sub_signed(elapsed)),
+    };
+    time.unwrap_or(DateTime::<Utc>::MAX_UTC)
+}
+
+// pending timers of the cursors, a single task drains them in deadline order
+// instead of one sleeping task per timer
+pub struct Timers<T> {
+    queue: Arc<Mutex<Queue<T>>>,
+}
+
+// the delay queue can not hold deadlines more than about two years away, so
+// timers further out are armed at this distance and re-armed when it expires
+const MAX_DELAY: Duration = Duration::from_secs(365 * 86400);
+
+struct Queue<T> {
+    // cursor id, value and the actual deadline of every timer
+    delays: DelayQueue<(String, T, Instant)>,
+    // keys of the pending timers, by cursor id
+    keys: HashMap<String, Vec<Key>>,
+}
+
+impl<T> Default for Timers<T> {
+    fn default() -> Self {
+        Self {
+            queue: Arc::new(Mutex::new(Queue {
+                delays: DelayQueue::new(),
+                keys: HashMap::new(),
+            })),
+        }
+    }
+}
+
+impl<T> Clone for Timers<T> {
+    fn clone(&self) -> Self {
+        Self {
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+impl<T> Timers<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // add a timer of the cursor expiring at the deadline
+    pub fn insert(&self, cursor: &str, deadline: Instant, value: T) {
+        self.queue
+            .lock()
+            .unwrap()
+            .insert(cursor.to_string(), deadline, value);
+    }
+
+    // drop all pending timers of the cursor
+    pub fn cancel(&self, cursor: &str) {
+        let mut queue = self.queue.lock().unwrap();
+        for key in queue.keys.remove(cursor).unwrap_or_default() {
+            queue.delays.try_remove(&key);
+        }
+    }
+
+    // whether the cursor has pending timers
+    pub fn contains(&self, cursor: &str) -> bool {
+        self.queue.lock().unwrap().keys.contains_key(cursor)
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.lock().unwrap().delays.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // wait for the next timer to expire, returns its cursor id and value
+    pub async fn expired(&self) -> (String, T) {
+        poll_fn(|cx| {
+            let mut queue = self.queue.lock().unwrap();
+            loop {
+                // an empty queue keeps the waker and is woken by the next insert
+                let Poll::Ready(Some(expired)) = queue.delays.poll_expired(cx) else {
+                    return Poll::Pending;
+                };
+
+                let key = expired.key();
+                let (cursor, value, deadline) = expired.into_inner();
+                if let Some(keys) = queue.keys.get_mut(&cursor) {
+                    keys.retain(|other| *other != key);
+                    if keys.is_empty() {
+                        queue.keys.remove(&cursor);
+                    }
+                }
+
+                if deadline <= Instant::now() {
+                    return Poll::Ready((cursor, value));
+                }
+                queue.insert(cursor, deadline, value);
+            }
+        })
+        .await
+    }
+}
+
+impl<T> Queue<T> {
+    fn insert(&mut self, cursor: String, deadline: Instant, value: T) {
+        let armed = deadline.min(Instant::now() + MAX_DELAY);
+        let key = self
+            .delays
+            .insert_at((cursor.clone(), value, deadline), armed);
+        self.keys.entry(cursor).or_default().push(key);
+    }
+}
+
+// serde helper storing durations as ISO-8601 strings
+pub mod iso8601 {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::format_duration(*duration))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        super::parse_duration(&text)
+            .map_err(|_| serde::de::Error::custom(format!("invalid duration: {}", text)))
+    }
+}
+
+// an instant as an RFC 3339 timestamp, so deadlines survive a restart
+pub mod rfc3339 {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use tokio::time::Instant;
+
+    pub fn serialize<S: Serializer>(instant: &Instant, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::time_at(*instant).to_rfc3339())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Instant, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        super::parse_timestamp(&text)
+            .map_err(|_| serde::de::Error::custom(format!("invalid timestamp: {}", text)))
+    }
+}
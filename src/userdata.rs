@@ -0,0 +1,335 @@
+use std::{cmp::Ordering, fmt::Write};
+
+use chrono::{DateTime, SecondsFormat, TimeDelta, Utc};
+use mlua::{Lua, MetaMethod, UserData, UserDataMethods};
+use rust_decimal::{prelude::ToPrimitive, Decimal, RoundingStrategy};
+
+use crate::{state::Variant, timer};
+
+// the variants lua has no type for, a timestamp, duration, decimal or bytes
+pub struct LuaVariant(pub Variant);
+
+// constructors for the lua side, `datetime()` is the current time
+pub fn register(lua: &Lua) -> mlua::Result<()> {
+    let globals = lua.globals();
+
+    globals.set(
+        "datetime",
+        lua.create_function(|_, text: Option<String>| {
+            let time = match text {
+                Some(text) => DateTime::parse_from_rfc3339(text.trim())
+                    .map_err(|_| mlua::Error::external("invalid timestamp"))?
+                    .with_timezone(&Utc),
+                None => Utc::now(),
+            };
+            Ok(Variant::DateTime(time))
+        })?,
+    )?;
+
+    // a number of seconds or an ISO-8601 duration, which may be negative
+    globals.set(
+        "duration",
+        lua.create_function(|_, value: mlua::Value| {
+            let delta = match value {
+                mlua::Value::Integer(seconds) => TimeDelta::try_seconds(seconds),
+                mlua::Value::Number(seconds) if seconds.is_finite() => {
+                    Some(TimeDelta::nanoseconds((seconds * 1e9).round() as i64))
+                }
+                mlua::Value::String(text) => timer::parse_delta(text.to_str()?).ok(),
+                _ => None,
+            }
+            .ok_or_else(|| mlua::Error::external("invalid duration"))?;
+            Ok(Variant::Duration(delta))
+        })?,
+    )?;
+
+    // floats are refused, they would bring their rounding errors along
+    globals.set(
+        "decimal",
+        lua.create_function(|_, value: mlua::Value| {
+            let decimal = match value {
+                mlua::Value::Integer(value) => Some(Decimal::from(value)),
+                mlua::Value::String(text) => text.to_str()?.trim().parse().ok(),
+                _ => None,
+            }
+            .ok_or_else(|| mlua::Error::external("decimal needs a string or an integer"))?;
+            Ok(Variant::Decimal(decimal))
+        })?,
+    )?;
+
+    globals.set(
+        "bytes",
+        lua.create_function(|_, text: mlua::String| Ok(Variant::Bytes(text.as_bytes().to_vec())))?,
+    )?;
+
+    Ok(())
+}
+
+impl UserData for LuaVariant {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("type", |_, this, ()| Ok(type_name(&this.0)));
+
+        methods.add_method("format", |_, this, format: String| match &this.0 {
+            Variant::DateTime(time) => {
+                let mut text = String::new();
+                write!(text, "{}", time.format(&format))
+                    .map_err(|_| mlua::Error::external("invalid format"))?;
+                Ok(text)
+            }
+            variant => Err(not_a_method("format", variant)),
+        });
+
+        methods.add_method("timestamp", |_, this, ()| match &this.0 {
+            Variant::DateTime(time) => Ok(time.timestamp()),
+            variant => Err(not_a_method("timestamp", variant)),
+        });
+
+        methods.add_method("seconds", |_, this, ()| match &this.0 {
+            Variant::Duration(delta) => Ok(delta
+                .num_nanoseconds()
+                .map_or(delta.num_milliseconds() as f64 / 1e3, |nanos| {
+                    nanos as f64 / 1e9
+                })),
+            variant => Err(not_a_method("seconds", variant)),
+        });
+
+        // rounds half away from zero, as money is
+        methods.add_method("round", |_, this, places: u32| match &this.0 {
+            Variant::Decimal(decimal) => Ok(Variant::Decimal(
+                decimal.round_dp_with_strategy(places, RoundingStrategy::MidpointAwayFromZero),
+            )),
+            variant => Err(not_a_method("round", variant)),
+        });
+
+        methods.add_method("tonumber", |_, this, ()| match &this.0 {
+            Variant::Decimal(decimal) => Ok(decimal.to_f64()),
+            variant => Err(not_a_method("tonumber", variant)),
+        });
+
+        methods.add_method("hex", |_, this, ()| match &this.0 {
+            Variant::Bytes(bytes) => Ok(bytes
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()),
+            variant => Err(not_a_method("hex", variant)),
+        });
+
+        for (method, op) in [
+            (MetaMethod::Add, Op::Add),
+            (MetaMethod::Sub, Op::Sub),
+            (MetaMethod::Mul, Op::Mul),
+            (MetaMethod::Div, Op::Div),
+            (MetaMethod::Mod, Op::Mod),
+        ] {
+            methods.add_meta_function(method, move |_, (a, b): (mlua::Value, mlua::Value)| {
+                arithmetic(op, operand(&a)?, operand(&b)?)
+            });
+        }
+
+        methods.add_meta_method(MetaMethod::Unm, |_, this, ()| match &this.0 {
+            Variant::Duration(delta) => Ok(Variant::Duration(-*delta)),
+            Variant::Decimal(decimal) => Ok(Variant::Decimal(-*decimal)),
+            variant => Err(mlua::Error::external(format!(
+                "cannot negate {}",
+                type_name(variant)
+            ))),
+        });
+
+        // lua only asks for equality when both sides are userdata
+        methods.add_meta_function(MetaMethod::Eq, |_, (a, b): (mlua::Value, mlua::Value)| {
+            Ok(operand(&a)? == operand(&b)?)
+        });
+        methods.add_meta_function(MetaMethod::Lt, |_, (a, b): (mlua::Value, mlua::Value)| {
+            Ok(compare(&operand(&a)?, &operand(&b)?)? == Ordering::Less)
+        });
+        methods.add_meta_function(MetaMethod::Le, |_, (a, b): (mlua::Value, mlua::Value)| {
+            Ok(compare(&operand(&a)?, &operand(&b)?)? != Ordering::Greater)
+        });
+
+        // bytes concatenate to bytes, anything else to a string
+        methods.add_meta_function(
+            MetaMethod::Concat,
+            |lua, (a, b): (mlua::Value, mlua::Value)| {
+                let (a, b) = (operand(&a)?, operand(&b)?);
+                if matches!(a, Variant::Bytes(_)) || matches!(b, Variant::Bytes(_)) {
+                    let mut bytes = to_bytes(&a)?;
+                    bytes.extend(to_bytes(&b)?);
+                    return Ok(mlua::Value::UserData(
+                        lua.create_userdata(LuaVariant(Variant::Bytes(bytes)))?,
+                    ));
+                }
+                let text = format!("{}{}", to_text(&a)?, to_text(&b)?);
+                Ok(mlua::Value::String(lua.create_string(text)?))
+            },
+        );
+
+        methods.add_meta_method(MetaMethod::Len, |_, this, ()| match &this.0 {
+            Variant::Bytes(bytes) => Ok(bytes.len()),
+            variant => Err(mlua::Error::external(format!(
+                "{} has no length",
+                type_name(variant)
+            ))),
+        });
+
+        methods.add_meta_method(MetaMethod::ToString, |lua, this, ()| match &this.0 {
+            Variant::Bytes(bytes) => lua.create_string(bytes),
+            variant => lua.create_string(to_text(variant)?),
+        });
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+impl Op {
+    fn verb(self) -> &'static str {
+        match self {
+            Op::Add => "add",
+            Op::Sub => "subtract",
+            Op::Mul => "multiply",
+            Op::Div => "divide",
+            Op::Mod => "take the remainder of",
+        }
+    }
+}
+
+fn arithmetic(op: Op, a: Variant, b: Variant) -> mlua::Result<Variant> {
+    use Variant::{DateTime, Duration};
+
+    let result = match (op, &a, &b) {
+        (Op::Add, DateTime(time), Duration(delta)) | (Op::Add, Duration(delta), DateTime(time)) => {
+            time.checked_add_signed(*delta).map(DateTime)
+        }
+        (Op::Sub, DateTime(time), Duration(delta)) => time.checked_sub_signed(*delta).map(DateTime),
+        (Op::Sub, DateTime(a), DateTime(b)) => Some(Duration(a.signed_duration_since(*b))),
+        (Op::Add, Duration(a), Duration(b)) => a.checked_add(b).map(Duration),
+        (Op::Sub, Duration(a), Duration(b)) => a.checked_sub(b).map(Duration),
+        (Op::Mul, Duration(delta), Variant::Integer(n))
+        | (Op::Mul, Variant::Integer(n), Duration(delta)) => i32::try_from(*n)
+            .ok()
+            .and_then(|n| delta.checked_mul(n))
+            .map(Duration),
+        (Op::Div, Duration(delta), Variant::Integer(n)) => i32::try_from(*n)
+            .ok()
+            .and_then(|n| delta.checked_div(n))
+            .map(Duration),
+        _ => match (decimal(&a), decimal(&b)) {
+            (Some(a), Some(b)) => match op {
+                Op::Add => a.checked_add(b),
+                Op::Sub => a.checked_sub(b),
+                Op::Mul => a.checked_mul(b),
+                Op::Div => a.checked_div(b),
+                Op::Mod => a.checked_rem(b),
+            }
+            .map(Variant::Decimal),
+            _ => {
+                return Err(mlua::Error::external(format!(
+                    "cannot {} {} and {}",
+                    op.verb(),
+                    type_name(&a),
+                    type_name(&b)
+                )))
+            }
+        },
+    };
+
+    result.ok_or_else(|| {
+        mlua::Error::external(format!(
+            "cannot {} {} and {}, the result is out of range",
+            op.verb(),
+            type_name(&a),
+            type_name(&b)
+        ))
+    })
+}
+
+// decimals mix with integers, never with floats
+fn decimal(variant: &Variant) -> Option<Decimal> {
+    match variant {
+        Variant::Decimal(decimal) => Some(*decimal),
+        Variant::Integer(value) => Some(Decimal::from(*value)),
+        _ => None,
+    }
+}
+
+fn compare(a: &Variant, b: &Variant) -> mlua::Result<Ordering> {
+    match (a, b) {
+        (Variant::DateTime(a), Variant::DateTime(b)) => Ok(a.cmp(b)),
+        (Variant::Duration(a), Variant::Duration(b)) => Ok(a.cmp(b)),
+        (Variant::Bytes(a), Variant::Bytes(b)) => Ok(a.cmp(b)),
+        _ => match (decimal(a), decimal(b)) {
+            (Some(a), Some(b)) => Ok(a.cmp(&b)),
+            _ => Err(mlua::Error::external(format!(
+                "cannot compare {} and {}",
+                type_name(a),
+                type_name(b)
+            ))),
+        },
+    }
+}
+
+// the plain lua values that take part in operations with the userdata
+fn operand(value: &mlua::Value) -> mlua::Result<Variant> {
+    match value {
+        mlua::Value::Integer(value) => Ok(Variant::Integer(*value)),
+        mlua::Value::Number(value) => Ok(Variant::Float(*value)),
+        mlua::Value::String(text) => Ok(Variant::String(text.to_str()?.to_string())),
+        mlua::Value::UserData(userdata) => Ok(userdata.borrow::<LuaVariant>()?.0.clone()),
+        value => Err(mlua::Error::external(format!(
+            "unexpected {} operand",
+            value.type_name()
+        ))),
+    }
+}
+
+fn to_text(variant: &Variant) -> mlua::Result<String> {
+    match variant {
+        Variant::String(text) => Ok(text.clone()),
+        Variant::Integer(value) => Ok(value.to_string()),
+        Variant::Float(value) => Ok(value.to_string()),
+        Variant::DateTime(time) => Ok(time.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+        Variant::Duration(delta) => Ok(timer::format_delta(*delta)),
+        Variant::Decimal(decimal) => Ok(decimal.to_string()),
+        variant => Err(mlua::Error::external(format!(
+            "cannot turn {} into a string",
+            type_name(variant)
+        ))),
+    }
+}
+
+fn to_bytes(variant: &Variant) -> mlua::Result<Vec<u8>> {
+    match variant {
+        Variant::Bytes(bytes) => Ok(bytes.clone()),
+        variant => Ok(to_text(variant)?.into_bytes()),
+    }
+}
+
+fn type_name(variant: &Variant) -> &'static str {
+    match variant {
+        Variant::Null => "nil",
+        Variant::String(_) => "string",
+        Variant::Integer(_) => "integer",
+        Variant::Float(_) => "float",
+        Variant::Boolean(_) => "boolean",
+        Variant::Array(_) => "array",
+        Variant::Object(_) => "object",
+        Variant::DateTime(_) => "datetime",
+        Variant::Duration(_) => "duration",
+        Variant::Decimal(_) => "decimal",
+        Variant::Bytes(_) => "bytes",
+    }
+}
+
+fn not_a_method(name: &str, variant: &Variant) -> mlua::Error {
+    mlua::Error::external(format!(
+        "`{}` is not a method of {}",
+        name,
+        type_name(variant)
+    ))
+}
@@ -0,0 +1,309 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+};
+
+use mlua::Lua;
+
+use crate::{
+    base::Executable,
+    flow::Flow,
+    node::{Node, NodeKind},
+    procedure::Procedure,
+};
+
+// a node paired with the flow leading to it, if any
+type Edge = (Arc<Node>, Option<Arc<Flow>>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Diagnostic {
+    // no node to start the procedure from
+    MissingStart,
+    // designated start node does not exist
+    UnknownStart { node: String },
+    // node can not be reached from any start node
+    Unreachable { node: String },
+    // flow endpoint is dropped or belongs to another procedure
+    ForeignFlow { flow: String, node: Option<String> },
+    // node has no outgoings but does not end the cursor explicitly
+    DeadEnd { node: String },
+    // nodes form a cycle without any condition or wait to break it
+    UnguardedCycle { nodes: Vec<String> },
+    // script or condition does not compile
+    SyntaxError { element: String, reason: String },
+}
+
+impl Diagnostic {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Diagnostic::Unreachable { .. } | Diagnostic::DeadEnd { .. } => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl Procedure {
+    // check the graph statically and report every problem found
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+        let nodes = sorted(&self.nodes);
+        let flows = sorted(&self.flows);
+
+        for flow in &flows {
+            for endpoint in [flow.source(), flow.target()] {
+                match endpoint.upgrade() {
+                    Some(node) if self.owns(&node) => {}
+                    node => diagnostics.push(Diagnostic::ForeignFlow {
+                        flow: flow.name.clone(),
+                        node: node.map(|node| node.name.clone()),
+                    }),
+                }
+            }
+        }
+
+        for name in &self.start {
+            if !self.nodes.contains_key(name) {
+                diagnostics.push(Diagnostic::UnknownStart { node: name.clone() });
+            }
+        }
+
+        let starts = self.start_nodes();
+        if starts.is_empty() && !nodes.is_empty() {
+            diagnostics.push(Diagnostic::MissingStart);
+        }
+
+        let reachable = self.reachable(&starts);
+        for node in &nodes {
+            if !starts.is_empty() && !reachable.contains(&node.name) {
+                diagnostics.push(Diagnostic::Unreachable {
+                    node: node.name.clone(),
+                });
+            }
+            if node.outgoings.is_empty() && !is_terminal(node) {
+                diagnostics.push(Diagnostic::DeadEnd {
+                    node: node.name.clone(),
+                });
+            }
+        }
+
+        for cycle in self.cycles() {
+            let guarded = cycle.iter().any(|(node, flow)| {
+                matches!(
+                    node.kind,
+                    NodeKind::Timer(_)
+                        | NodeKind::ExclusiveGateway
+                        | NodeKind::InclusiveGateway
+                        | NodeKind::EventGateway
+                        | NodeKind::Message { .. }
+                        | NodeKind::Signal { .. }
+                ) || node.script.contains("set_wait")
+                    || flow.as_ref().is_some_and(|flow| !flow.condition.is_empty())
+            });
+            if !guarded {
+                let mut names: Vec<String> =
+                    cycle.iter().map(|(node, _)| node.name.clone()).collect();
+                names.sort();
+                names.dedup();
+                diagnostics.push(Diagnostic::UnguardedCycle { nodes: names });
+            }
+        }
+
+        let lua = Lua::new();
+        let mut compile = |element: &str, source: String| {
+            if let Err(error) = lua.load(source).set_name(element).into_function() {
+                diagnostics.push(Diagnostic::SyntaxError {
+                    element: element.to_string(),
+                    reason: error.to_string(),
+                });
+            }
+        };
+        for node in &nodes {
+            compile(&node.name, node.script.clone());
+            compile(&node.name, node.on_cancel.clone());
+        }
+        for flow in &flows {
+            compile(&flow.name, flow.script.clone());
+            if !flow.condition.is_empty() {
+                compile(&flow.name, format!("return ({})", flow.condition));
+            }
+        }
+
+        diagnostics
+    }
+
+    fn owns(&self, node: &Arc<Node>) -> bool {
+        self.nodes
+            .get(&node.name)
+            .is_some_and(|owned| Arc::ptr_eq(owned, node))
+    }
+
+    fn reachable(&self, starts: &[Arc<Node>]) -> HashSet<String> {
+        let mut visited = HashSet::new();
+        let mut queue: VecDeque<Arc<Node>> = starts.iter().cloned().collect();
+        while let Some(node) = queue.pop_front() {
+            if !visited.insert(node.name.clone()) {
+                continue;
+            }
+            for (next, _) in successors(&node) {
+                if self.owns(&next) {
+                    queue.push_back(next);
+                }
+            }
+        }
+        visited
+    }
+
+    // strongly connected components that contain a cycle, each as the nodes
+    // in it paired with the flow that leads to them inside the component
+    fn cycles(&self) -> Vec<Vec<Edge>> {
+        struct Tarjan<'a> {
+            procedure: &'a Procedure,
+            index: usize,
+            indices: HashMap<String, usize>,
+            lowlinks: HashMap<String, usize>,
+            stack: Vec<Arc<Node>>,
+            on_stack: HashSet<String>,
+            components: Vec<Vec<Arc<Node>>>,
+        }
+
+        impl Tarjan<'_> {
+            // depth first with an explicit stack, so long chains of nodes do
+            // not overflow the thread's stack
+            fn visit(&mut self, root: &Arc<Node>) {
+                let mut frames = vec![self.enter(root)];
+                while let Some((node, successors)) = frames.last_mut() {
+                    let node = node.clone();
+                    match successors.next() {
+                        Some(next) if !self.indices.contains_key(&next.name) => {
+                            let frame = self.enter(&next);
+                            frames.push(frame);
+                        }
+                        Some(next) => {
+                            if self.on_stack.contains(&next.name) {
+                                let lowlink =
+                                    self.lowlinks[&node.name].min(self.indices[&next.name]);
+                                self.lowlinks.insert(node.name.clone(), lowlink);
+                            }
+                        }
+                        None => {
+                            frames.pop();
+                            self.leave(&node);
+                            if let Some((parent, _)) = frames.last() {
+                                let lowlink =
+                                    self.lowlinks[&parent.name].min(self.lowlinks[&node.name]);
+                                self.lowlinks.insert(parent.name.clone(), lowlink);
+                            }
+                        }
+                    }
+                }
+            }
+
+            // number the node and put it on the stack, with the successors
+            // left to visit
+            fn enter(&mut self, node: &Arc<Node>) -> (Arc<Node>, std::vec::IntoIter<Arc<Node>>) {
+                self.indices.insert(node.name.clone(), self.index);
+                self.lowlinks.insert(node.name.clone(), self.index);
+                self.index += 1;
+                self.stack.push(node.clone());
+                self.on_stack.insert(node.name.clone());
+
+                let successors: Vec<Arc<Node>> = successors(node)
+                    .into_iter()
+                    .map(|(next, _)| next)
+                    .filter(|next| self.procedure.owns(next))
+                    .collect();
+                (node.clone(), successors.into_iter())
+            }
+
+            // once all successors are visited, a root node closes its component
+            fn leave(&mut self, node: &Arc<Node>) {
+                if self.lowlinks[&node.name] == self.indices[&node.name] {
+                    let mut component = vec![];
+                    while let Some(member) = self.stack.pop() {
+                        self.on_stack.remove(&member.name);
+                        let done = member.name == node.name;
+                        component.push(member);
+                        if done {
+                            break;
+                        }
+                    }
+                    self.components.push(component);
+                }
+            }
+        }
+
+        let mut tarjan = Tarjan {
+            procedure: self,
+            index: 0,
+            indices: HashMap::new(),
+            lowlinks: HashMap::new(),
+            stack: vec![],
+            on_stack: HashSet::new(),
+            components: vec![],
+        };
+        for node in sorted(&self.nodes) {
+            if !tarjan.indices.contains_key(&node.name) {
+                tarjan.visit(&node);
+            }
+        }
+
+        tarjan
+            .components
+            .into_iter()
+            .filter_map(|component| {
+                let names: HashSet<&String> = component.iter().map(|node| &node.name).collect();
+                let mut members = vec![];
+                let mut has_edge = false;
+                for node in &component {
+                    members.push((node.clone(), None));
+                    for (next, flow) in successors(node) {
+                        if names.contains(&next.name) {
+                            has_edge = true;
+                            members.push((next, flow));
+                        }
+                    }
+                }
+                has_edge.then_some(members)
+            })
+            .collect()
+    }
+}
+
+// a node without outgoings ends the cursor by itself only when it has no
+// script to decide otherwise or the script completes or bubbles
+fn is_terminal(node: &Node) -> bool {
+    node.kind == NodeKind::Task
+        && (node.script.is_empty()
+            || node.script.contains("set_complete")
+            || node.script.contains("set_bubble"))
+}
+
+// next nodes with the flow leading to them
+fn successors(node: &Node) -> Vec<Edge> {
+    node.outgoings
+        .iter()
+        .filter_map(|outgoing| match outgoing {
+            Executable::Node(next) => Some((next.upgrade()?, None)),
+            Executable::Flow(flow) => {
+                let flow = flow.upgrade()?;
+                Some((flow.target().upgrade()?, Some(flow)))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn sorted<T>(map: &HashMap<String, Arc<T>>) -> Vec<Arc<T>> {
+    let mut entries: Vec<(&String, &Arc<T>)> = map.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+        .into_iter()
+        .map(|(_, value)| value.clone())
+        .collect()
+}
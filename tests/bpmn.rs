@@ -0,0 +1,193 @@
+use std::{sync::Arc, time::Duration};
+
+use donut::{
+    definition::ProcedureDefinition, error::Error, node::NodeKind, procedure::Procedure,
+    scheduler::Scheduler, state::Variant,
+};
+use tokio::sync::RwLock;
+
+fn process(elements: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<bpmn:definitions xmlns:bpmn="http://www.omg.org/spec/BPMN/20100524/MODEL">
+  <bpmn:process id="order" name="Order">
+    {}
+  </bpmn:process>
+</bpmn:definitions>"#,
+        elements
+    )
+}
+
+fn kind<'a>(definition: &'a ProcedureDefinition, name: &str) -> &'a NodeKind {
+    &definition
+        .nodes
+        .iter()
+        .find(|node| node.name == name)
+        .unwrap()
+        .kind
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn script_tasks_continue_after_their_script() {
+    let source = process(
+        r#"
+    <bpmn:startEvent id="start" />
+    <bpmn:scriptTask id="pack" scriptFormat="lua">
+      <bpmn:script>set_state('packed', true)</bpmn:script>
+    </bpmn:scriptTask>
+    <bpmn:endEvent id="end" />
+    <bpmn:sequenceFlow id="to_pack" sourceRef="start" targetRef="pack" />
+    <bpmn:sequenceFlow id="to_end" sourceRef="pack" targetRef="end" />
+    "#,
+    );
+    let definition = ProcedureDefinition::from_bpmn(&source).unwrap();
+    assert_eq!(definition.name, "Order");
+    assert_eq!(definition.start, vec!["start".to_string()]);
+
+    let procedure = Arc::new(Procedure::from_definition(&definition).unwrap());
+    let scheduler = Arc::new(RwLock::new(Scheduler::new()));
+    let cursors = Scheduler::start_procedure(scheduler.clone(), vec![procedure.clone()])
+        .await
+        .unwrap();
+    tokio::time::timeout(Duration::from_secs(5), Scheduler::join(scheduler))
+        .await
+        .expect("the procedure did not finish")
+        .unwrap();
+
+    let cursor = cursors[0].read().await;
+    assert!(cursor.is_complete());
+    assert_eq!(*cursor.current(), procedure.find("end").unwrap());
+    assert!(cursor.context().state.get("packed") == Some(&Variant::Boolean(true)));
+}
+
+#[test]
+fn gateways_and_default_flows() {
+    let source = process(
+        r#"
+    <bpmn:startEvent id="start" />
+    <bpmn:exclusiveGateway id="choice" default="manual" />
+    <bpmn:inclusiveGateway id="fork" default="fallback" />
+    <bpmn:parallelGateway id="split" />
+    <bpmn:parallelGateway id="merge" />
+    <bpmn:task id="a" />
+    <bpmn:task id="b" />
+    <bpmn:endEvent id="end" />
+    <bpmn:sequenceFlow id="manual" sourceRef="choice" targetRef="a" />
+    <bpmn:sequenceFlow id="auto" sourceRef="choice" targetRef="b">
+      <bpmn:conditionExpression>total &lt; 100</bpmn:conditionExpression>
+    </bpmn:sequenceFlow>
+    <bpmn:sequenceFlow id="small" sourceRef="fork" targetRef="a">
+      <bpmn:conditionExpression>small</bpmn:conditionExpression>
+    </bpmn:sequenceFlow>
+    <bpmn:sequenceFlow id="large" sourceRef="fork" targetRef="b">
+      <bpmn:conditionExpression>large</bpmn:conditionExpression>
+    </bpmn:sequenceFlow>
+    <bpmn:sequenceFlow id="fallback" sourceRef="fork" targetRef="end" />
+    <bpmn:sequenceFlow id="left" sourceRef="split" targetRef="merge" />
+    <bpmn:sequenceFlow id="right" sourceRef="split" targetRef="merge" />
+    "#,
+    );
+    let definition = ProcedureDefinition::from_bpmn(&source).unwrap();
+
+    assert_eq!(*kind(&definition, "choice"), NodeKind::ExclusiveGateway);
+    assert_eq!(*kind(&definition, "fork"), NodeKind::InclusiveGateway);
+    assert_eq!(*kind(&definition, "split"), NodeKind::ParallelGateway);
+    assert!(matches!(
+        kind(&definition, "merge"),
+        NodeKind::Join { count: None, .. }
+    ));
+    assert_eq!(*kind(&definition, "a"), NodeKind::Task);
+
+    // the default flow of an exclusive gateway is tried last
+    let names: Vec<&str> = definition
+        .flows
+        .iter()
+        .map(|flow| flow.name.as_str())
+        .collect();
+    assert_eq!(names.last(), Some(&"manual"));
+    let auto = definition.flows.iter().find(|flow| flow.name == "auto");
+    assert_eq!(auto.unwrap().condition, "total < 100");
+
+    // the default flow of an inclusive gateway is taken when no other is
+    let fallback = definition.flows.iter().find(|flow| flow.name == "fallback");
+    assert_eq!(fallback.unwrap().condition, "not ((small) or (large))");
+}
+
+#[test]
+fn timer_catch_events() {
+    let source = process(
+        r#"
+    <bpmn:intermediateCatchEvent id="wait">
+      <bpmn:timerEventDefinition>
+        <bpmn:timeDuration>PT5M</bpmn:timeDuration>
+      </bpmn:timerEventDefinition>
+    </bpmn:intermediateCatchEvent>
+    "#,
+    );
+    let definition = ProcedureDefinition::from_bpmn(&source).unwrap();
+    assert_eq!(
+        *kind(&definition, "wait"),
+        NodeKind::Timer(Duration::from_secs(300))
+    );
+
+    let source = process(r#"<bpmn:intermediateCatchEvent id="wait" />"#);
+    assert!(matches!(
+        ProcedureDefinition::from_bpmn(&source),
+        Err(Error::InvalidDefinition { .. })
+    ));
+}
+
+#[test]
+fn unsupported_script_formats_are_rejected() {
+    let source = process(
+        r#"
+    <bpmn:scriptTask id="pack" scriptFormat="javascript">
+      <bpmn:script>packed = true</bpmn:script>
+    </bpmn:scriptTask>
+    "#,
+    );
+    match ProcedureDefinition::from_bpmn(&source) {
+        Err(Error::InvalidDefinition { reason }) => assert!(reason.contains("javascript")),
+        result => panic!("unexpected {:?}", result),
+    }
+
+    assert!(matches!(
+        ProcedureDefinition::from_bpmn("<definitions />"),
+        Err(Error::InvalidDefinition { .. })
+    ));
+}
+
+#[test]
+fn unsupported_elements_are_rejected() {
+    for (tag, id) in [
+        ("userTask", "approve"),
+        ("serviceTask", "charge"),
+        ("subProcess", "ship"),
+        ("boundaryEvent", "late"),
+    ] {
+        let source = process(&format!(
+            r#"
+    <bpmn:startEvent id="start" />
+    <bpmn:{tag} id="{id}" />
+    <bpmn:sequenceFlow id="to_{id}" sourceRef="start" targetRef="{id}" />
+    "#
+        ));
+        match ProcedureDefinition::from_bpmn(&source) {
+            Err(Error::InvalidDefinition { reason }) => {
+                assert!(reason.contains(tag) && reason.contains(id), "{}", reason)
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    // documentation and artifacts are ignored
+    let source = process(
+        r#"
+    <bpmn:documentation>an order</bpmn:documentation>
+    <bpmn:startEvent id="start" />
+    <bpmn:textAnnotation id="note"><bpmn:text>hi</bpmn:text></bpmn:textAnnotation>
+    "#,
+    );
+    let definition = ProcedureDefinition::from_bpmn(&source).unwrap();
+    assert_eq!(definition.nodes.len(), 1);
+}
@@ -0,0 +1,46 @@
+// helpers shared by the integration tests, not every test uses all of them
+#![allow(dead_code)]
+
+use std::{future::Future, sync::Arc, time::Duration};
+
+use donut::{
+    cursor::Cursor, definition::ProcedureDefinition, procedure::Procedure, scheduler::Scheduler,
+};
+use tokio::sync::RwLock;
+
+// a procedure from its yaml definition
+pub fn procedure(source: &str) -> Arc<Procedure> {
+    let definition = ProcedureDefinition::from_yaml(source).unwrap();
+    Arc::new(Procedure::from_definition(&definition).unwrap())
+}
+
+// start the procedures on a new scheduler
+pub async fn start(
+    procedures: Vec<Arc<Procedure>>,
+) -> (Arc<RwLock<Scheduler>>, Vec<Arc<RwLock<Cursor>>>) {
+    let scheduler = Arc::new(RwLock::new(Scheduler::new()));
+    let cursors = Scheduler::start_procedure(scheduler.clone(), procedures)
+        .await
+        .unwrap();
+    (scheduler, cursors)
+}
+
+// yield until the condition holds, failing the test instead of hanging
+pub async fn until<F: Future<Output = bool>>(mut condition: impl FnMut() -> F, what: &str) {
+    let wait = async {
+        while !condition().await {
+            tokio::task::yield_now().await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), wait)
+        .await
+        .unwrap_or_else(|_| panic!("timed out waiting until {}", what));
+}
+
+// until every cursor waits for events or a deadline
+pub async fn wait_for(scheduler: &Arc<RwLock<Scheduler>>, cursors: &[Arc<RwLock<Cursor>>]) {
+    for cursor in cursors {
+        let id = cursor.read().await.id().to_string();
+        until(|| Scheduler::is_waiting(scheduler, &id), "the cursor waits").await;
+    }
+}
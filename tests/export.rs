@@ -0,0 +1,99 @@
+use donut::{
+    base::Executable,
+    definition::ProcedureDefinition,
+    export::{to_dot, to_mermaid},
+    procedure::Procedure,
+};
+
+const SHIPPING: &str = r#"
+name: 'ship "it"'
+start: [start]
+nodes:
+  - name: start
+  - name: check
+    kind: exclusive_gateway
+  - name: 'say "hi"'
+    script: set_complete()
+  - name: escalate
+    script: set_bubble()
+  - name: wait
+    kind: !timer PT5M
+flows:
+  - { name: to_check, source: start, target: check }
+  - { name: greet, source: check, target: 'say "hi"', condition: 'a | b == "x"' }
+  - { name: to_wait, source: check, target: wait, condition: 'total <= 0' }
+  - { name: to_escalate, source: wait, target: escalate }
+"#;
+
+fn procedure() -> Procedure {
+    Procedure::from_definition(&ProcedureDefinition::from_yaml(SHIPPING).unwrap()).unwrap()
+}
+
+#[test]
+fn dot_output() {
+    // flows are listed by name
+    let procedure = procedure();
+    assert_eq!(
+        to_dot(&procedure, None),
+        r#"digraph "ship \"it\"" {
+    rankdir=LR;
+    "check" [label="check", shape=diamond];
+    "escalate" [label="escalate", shape=box, style=dashed];
+    "say \"hi\"" [label="say \"hi\"", shape=box, peripheries=2];
+    "start" [label="start", shape=box];
+    "wait" [label="wait\nPT300S", shape=circle];
+    "check" -> "say \"hi\"" [label="a | b == \"x\""];
+    "start" -> "check";
+    "wait" -> "escalate";
+    "check" -> "wait" [label="total <= 0"];
+}
+"#
+    );
+}
+
+#[test]
+fn dot_highlights_the_current_elements() {
+    let procedure = procedure();
+    let node = procedure.find("check").unwrap();
+    let dot = to_dot(&procedure, Some(&node));
+    assert!(dot.contains(r#"    "check" [label="check", shape=diamond, color=red];"#));
+    assert_eq!(dot.matches("color=red").count(), 1);
+
+    let flow = procedure.find("to_wait").unwrap();
+    let dot = to_dot(&procedure, Some(&flow));
+    assert!(dot.contains(r#"    "check" -> "wait" [label="total <= 0", color=red];"#));
+    assert_eq!(dot.matches("color=red").count(), 1);
+}
+
+#[test]
+fn mermaid_output() {
+    let procedure = procedure();
+    let flows = ["greet", "to_wait"].map(|name| match procedure.find(name).unwrap() {
+        Executable::Flow(flow) => flow,
+        _ => unreachable!(),
+    });
+    let selection = Executable::Selection(flows.to_vec());
+    assert_eq!(
+        to_mermaid(&procedure, Some(&selection)),
+        r#"flowchart LR
+    n0{"check"}
+    n1["escalate"]
+    n2["say #quot;hi#quot;"]
+    n3["start"]
+    n4(("wait<br/>PT300S"))
+    n0 -->|"a #124; b == #quot;x#quot;"| n2
+    n3 --> n0
+    n4 --> n1
+    n0 -->|"total <= 0"| n4
+    classDef complete stroke-width:4px
+    class n2 complete
+    classDef bubble stroke-dasharray:5 5
+    class n1 bubble
+    linkStyle 0,3 stroke:#f00
+"#
+    );
+
+    let node = procedure.find("start").unwrap();
+    let mermaid = to_mermaid(&procedure, Some(&node));
+    assert!(mermaid.ends_with("    classDef current stroke:#f00\n    class n3 current\n"));
+}
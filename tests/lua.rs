@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+
+use donut::state::Variant;
+use mlua::{FromLua, Lua};
+
+fn line(sku: &str, quantity: i64) -> Variant {
+    let mut line = HashMap::new();
+    line.insert("sku".to_string(), Variant::String(sku.to_string()));
+    line.insert("quantity".to_string(), Variant::Integer(quantity));
+    Variant::Object(line)
+}
+
+fn eval(lua: &Lua, source: &str) -> mlua::Result<Variant> {
+    lua.load(source).eval::<Variant>()
+}
+
+#[test]
+fn nested_values_round_trip() {
+    let lua = Lua::new();
+    let mut order = HashMap::new();
+    order.insert(
+        "lines".to_string(),
+        Variant::Array(vec![line("A-1", 2), line("B-2", 1), line("C-3", 5)]),
+    );
+    order.insert("tags".to_string(), Variant::Object(HashMap::new()));
+    order.insert("notes".to_string(), Variant::Array(vec![]));
+    let order = Variant::Object(order);
+
+    lua.globals().set("order", &order).unwrap();
+    assert_eq!(eval(&lua, "return order").unwrap(), order);
+    assert_eq!(
+        eval(&lua, "return order.lines[2].sku").unwrap(),
+        Variant::String("B-2".to_string())
+    );
+}
+
+#[test]
+fn arrays_keep_their_order_and_holes() {
+    let lua = Lua::new();
+    let array = eval(
+        &lua,
+        "local t = {} t[3] = 'c' t[1] = 'a' t[2] = 'b' return t",
+    )
+    .unwrap();
+    let expected = ["a", "b", "c"].map(|s| Variant::String(s.to_string()));
+    assert_eq!(array, Variant::Array(expected.to_vec()));
+
+    let holes = eval(&lua, "return {1, nil, 3}").unwrap();
+    assert_eq!(
+        holes,
+        Variant::Array(vec![
+            Variant::Integer(1),
+            Variant::Null,
+            Variant::Integer(3)
+        ])
+    );
+
+    // too sparse to be an array
+    let sparse = eval(&lua, "return {[1] = 'a', [100] = 'b'}").unwrap();
+    let Variant::Object(sparse) = sparse else {
+        panic!("expected an object");
+    };
+    assert_eq!(sparse.get("100"), Some(&Variant::String("b".to_string())));
+}
+
+#[test]
+fn mixed_tables_become_objects() {
+    let lua = Lua::new();
+    let mixed = eval(&lua, "return {'a', 'b', total = 2, [true] = 1}").unwrap();
+    let Variant::Object(mixed) = mixed else {
+        panic!("expected an object");
+    };
+    assert_eq!(mixed.len(), 4);
+    assert_eq!(mixed.get("1"), Some(&Variant::String("a".to_string())));
+    assert_eq!(mixed.get("total"), Some(&Variant::Integer(2)));
+    assert_eq!(mixed.get("true"), Some(&Variant::Integer(1)));
+
+    assert!(eval(&lua, "return {[1] = 'a', ['1'] = 'b', x = 1}").is_err());
+    assert!(eval(&lua, "return {[{}] = 1}").is_err());
+}
+
+#[test]
+fn cycles_are_rejected_but_shared_tables_are_not() {
+    let lua = Lua::new();
+    assert!(eval(&lua, "local t = {} t.self = t return t").is_err());
+    assert!(eval(&lua, "local t = {x = {}} t.x.up = t return t").is_err());
+
+    let shared = eval(&lua, "local t = {1} return {a = t, b = t}").unwrap();
+    let Variant::Object(shared) = shared else {
+        panic!("expected an object");
+    };
+    assert_eq!(shared.get("a"), shared.get("b"));
+
+    let value = lua.load("return {n = 1.5}").eval::<mlua::Value>().unwrap();
+    assert!(Variant::from_lua(value, &lua).is_ok());
+}
+
+fn rich_lua() -> Lua {
+    let lua = Lua::new();
+    donut::userdata::register(&lua).unwrap();
+    lua
+}
+
+#[test]
+fn decimals_keep_money_exact() {
+    let lua = rich_lua();
+    assert_eq!(
+        eval(&lua, "return decimal('0.1') + decimal('0.2')").unwrap(),
+        Variant::Decimal("0.3".parse().unwrap())
+    );
+    assert_eq!(
+        eval(&lua, "return (decimal('19.99') * 3 / 7):round(2)").unwrap(),
+        Variant::Decimal("8.57".parse().unwrap())
+    );
+    assert_eq!(
+        eval(&lua, "return tostring(-decimal('2.50') % 2)").unwrap(),
+        Variant::String("-0.50".to_string())
+    );
+    assert_eq!(
+        eval(
+            &lua,
+            "return decimal('10.0') == decimal('10') and decimal('9.99') < 10"
+        )
+        .unwrap(),
+        Variant::Boolean(true)
+    );
+    assert!(eval(&lua, "return decimal('1') + 0.5").is_err());
+    assert!(eval(&lua, "return decimal('1') / 0").is_err());
+    assert!(eval(&lua, "return decimal(0.1)").is_err());
+
+    let values = [
+        Variant::Decimal("1.10".parse().unwrap()),
+        Variant::Integer(2),
+    ];
+    let total = donut::script::merge(
+        "local sum = decimal(0) for _, v in ipairs(values) do sum = sum + v end return sum",
+        "total",
+        None,
+        &values,
+    )
+    .unwrap();
+    assert_eq!(total, Variant::Decimal("3.10".parse().unwrap()));
+}
+
+#[test]
+fn timestamps_and_durations_do_calendar_math() {
+    let lua = rich_lua();
+    let source = r#"
+        local placed = datetime('2026-10-18T09:00:00Z')
+        local due = placed + duration('P2D') - duration(30)
+        return {
+            due = due,
+            late = datetime('2026-10-21T00:00:00Z') > due,
+            span = due - placed,
+            half = (due - placed) / 2,
+            text = 'due ' .. due,
+            day = due:format('%A'),
+            seconds = duration('PT1.5S'):seconds(),
+        }
+    "#;
+    let Variant::Object(values) = eval(&lua, source).unwrap() else {
+        panic!("expected an object");
+    };
+    let due: chrono::DateTime<chrono::Utc> = "2026-10-20T08:59:30Z".parse().unwrap();
+    assert_eq!(values["due"], Variant::DateTime(due));
+    assert_eq!(values["late"], Variant::Boolean(true));
+    let span = chrono::TimeDelta::seconds(2 * 86400 - 30);
+    assert_eq!(values["span"], Variant::Duration(span));
+    assert_eq!(values["half"], Variant::Duration(span / 2));
+    assert_eq!(
+        values["text"],
+        Variant::String("due 2026-10-20T08:59:30Z".to_string())
+    );
+    assert_eq!(values["day"], Variant::String("Tuesday".to_string()));
+    assert_eq!(values["seconds"], Variant::Float(1.5));
+
+    assert!(eval(&lua, "return datetime('2026-10-18T09:00:00Z') + 1").is_err());
+    assert!(eval(&lua, "return duration(1) < decimal('1')").is_err());
+}
+
+#[test]
+fn rich_values_pass_through_lua() {
+    let lua = rich_lua();
+    let digest = Variant::Bytes(vec![0xde, 0xad]);
+    lua.globals().set("digest", &digest).unwrap();
+    assert_eq!(eval(&lua, "return digest").unwrap(), digest);
+    assert_eq!(
+        eval(&lua, "return {#digest, digest:hex(), digest:type()}").unwrap(),
+        Variant::Array(vec![
+            Variant::Integer(2),
+            Variant::String("dead".to_string()),
+            Variant::String("bytes".to_string()),
+        ])
+    );
+    assert_eq!(
+        eval(&lua, "return digest .. bytes('!')").unwrap(),
+        Variant::Bytes(vec![0xde, 0xad, b'!'])
+    );
+    assert!(eval(&lua, "return digest:round(2)").is_err());
+}
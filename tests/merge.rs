@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use donut::{
+    error::Error,
+    merge::{merge, MergeStrategy},
+    state::{State, Variant},
+};
+
+fn state(entries: &[(&str, i64)]) -> State {
+    let mut state = State::new();
+    for (key, value) in entries {
+        state.set(key.to_string(), Variant::Integer(*value));
+    }
+    state
+}
+
+// children with the ids `a`, `b`, ... in order
+fn children<const N: usize>(states: [State; N]) -> Vec<(String, State)> {
+    states
+        .into_iter()
+        .zip('a'..)
+        .map(|(state, id)| (id.to_string(), state))
+        .collect()
+}
+
+fn get(state: &State, key: &str) -> Option<i64> {
+    match state.get(key) {
+        Some(Variant::Integer(value)) => Some(*value),
+        _ => None,
+    }
+}
+
+#[test]
+fn last_writer_wins_applies_changes_in_order() {
+    let parent = state(&[("shared", 0), ("kept", 1), ("dropped", 2)]);
+    let first = state(&[("shared", 1), ("kept", 1), ("dropped", 2)]);
+    let second = state(&[("shared", 2), ("kept", 1), ("extra", 3)]);
+
+    let merged = merge(
+        &MergeStrategy::LastWriterWins,
+        &parent,
+        &children([first, second]),
+    )
+    .unwrap();
+    assert_eq!(get(&merged, "shared"), Some(2));
+    assert_eq!(get(&merged, "kept"), Some(1));
+    assert_eq!(get(&merged, "extra"), Some(3));
+    assert!(!merged.has("dropped"));
+}
+
+#[test]
+fn fail_on_conflict_rejects_different_writes() {
+    let parent = state(&[("total", 0)]);
+    let same = children([state(&[("total", 5)]), state(&[("total", 5)])]);
+    let merged = merge(&MergeStrategy::FailOnConflict, &parent, &same).unwrap();
+    assert_eq!(get(&merged, "total"), Some(5));
+
+    let different = children([state(&[("total", 5)]), state(&[("total", 6)])]);
+    match merge(&MergeStrategy::FailOnConflict, &parent, &different) {
+        Err(Error::MergeConflict { key }) => assert_eq!(key, "total"),
+        _ => panic!("expected a merge conflict"),
+    }
+}
+
+#[test]
+fn collect_keeps_the_value_of_every_changing_child() {
+    let parent = state(&[("item", 0), ("gone", 1)]);
+    let mut removed = state(&[("item", 0)]);
+    removed.set("extra".to_string(), Variant::Null);
+    let children = children([
+        state(&[("item", 1), ("gone", 1)]),
+        state(&[("item", 0), ("gone", 1)]),
+        removed,
+    ]);
+
+    let merged = merge(&MergeStrategy::Collect, &parent, &children).unwrap();
+    // `b` left the item as it was, so it is not listed
+    let item = HashMap::from([("a".to_string(), Variant::Integer(1))]);
+    assert!(merged.get("item") == Some(&Variant::Object(item)));
+    // removing a key and writing null both show up as null
+    let gone = HashMap::from([("c".to_string(), Variant::Null)]);
+    assert!(merged.get("gone") == Some(&Variant::Object(gone)));
+    let extra = HashMap::from([("c".to_string(), Variant::Null)]);
+    assert!(merged.get("extra") == Some(&Variant::Object(extra)));
+}
+
+#[test]
+fn script_computes_the_merged_value() {
+    let parent = state(&[("sum", 10)]);
+    let children = children([state(&[("sum", 11)]), state(&[("sum", 12)])]);
+    let strategy = MergeStrategy::Script(
+        "local total = current for i = 1, #values do total = total + values[i] - current end return total"
+            .to_string(),
+    );
+
+    let merged = merge(&strategy, &parent, &children).unwrap();
+    assert_eq!(get(&merged, "sum"), Some(13));
+}
@@ -0,0 +1,858 @@
+mod common;
+
+use std::sync::Arc;
+
+use donut::{
+    base::{Executable, ExecutableKey, Next},
+    cursor::{Cursor, History},
+    definition::ProcedureDefinition,
+    error::Error,
+    procedure::Procedure,
+    scheduler::Scheduler,
+    state::{State, Variant},
+};
+use tokio::{sync::RwLock, time::Instant};
+
+const PROCEDURE: &str = r#"
+name: next
+start: [start]
+nodes:
+  - name: start
+  - name: idle
+    script: x = 1
+  - name: continue
+    script: set_continue()
+  - name: one
+    script: set_one('end')
+  - name: complete
+    script: set_complete()
+  - name: bubble
+    script: set_bubble()
+  - name: fork
+    kind: parallel_gateway
+  - name: choice
+    kind: exclusive_gateway
+  - name: timer
+    kind: !timer PT1M
+  - name: remind
+    script: set_wait('end', 'PT5M')
+  - name: retry
+    script: set_wait('end', 1.5)
+  - name: overdue
+    script: set_wait_until('end', '2000-01-01T00:00:00Z')
+  - name: nightly
+    script: set_wait_cron('end', '0 2 * * *')
+  - name: spread
+    script: set_parallel({'end', 'later'})
+  - name: race
+    script: set_select({'always', 'end'})
+  - name: end
+flows:
+  - { name: to_idle, source: start, target: idle }
+  - { name: left, source: fork, target: end }
+  - { name: right, source: fork, target: complete }
+  - { name: never, source: choice, target: end, condition: 'false' }
+  - { name: always, source: choice, target: complete, condition: 'true' }
+  - { name: later, source: timer, target: end }
+  - { name: action, source: continue, target: end, script: "set_one('bubble')" }
+  - { name: plain, source: one, target: end, script: "x = 1" }
+"#;
+
+async fn setup() -> (Arc<RwLock<Scheduler>>, Arc<Procedure>, Arc<RwLock<Cursor>>) {
+    let procedure = common::procedure(PROCEDURE);
+    let scheduler = Arc::new(RwLock::new(Scheduler::new()));
+    let cursor =
+        Cursor::from_procedure(Arc::downgrade(&scheduler), Arc::downgrade(&procedure)).await;
+    (scheduler, procedure, cursor)
+}
+
+async fn execute(procedure: &Procedure, name: &str, cursor: &Arc<RwLock<Cursor>>) -> Next {
+    procedure
+        .find(name)
+        .unwrap()
+        .execute(cursor.clone())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn script_without_routing_returns_null() {
+    let (_scheduler, procedure, cursor) = setup().await;
+    assert_eq!(execute(&procedure, "idle", &cursor).await, Next::Null);
+}
+
+#[tokio::test]
+async fn set_continue_returns_continue() {
+    let (_scheduler, procedure, cursor) = setup().await;
+    assert_eq!(
+        execute(&procedure, "continue", &cursor).await,
+        Next::Continue
+    );
+    assert_eq!(execute(&procedure, "end", &cursor).await, Next::Continue);
+}
+
+#[tokio::test]
+async fn set_one_returns_one() {
+    let (_scheduler, procedure, cursor) = setup().await;
+    assert_eq!(
+        execute(&procedure, "one", &cursor).await,
+        Next::One(procedure.find("end").unwrap())
+    );
+}
+
+#[tokio::test]
+async fn parallel_gateway_returns_parallel() {
+    let (_scheduler, procedure, cursor) = setup().await;
+    assert_eq!(
+        execute(&procedure, "fork", &cursor).await,
+        Next::Parallel(vec![
+            procedure.find("left").unwrap(),
+            procedure.find("right").unwrap(),
+        ])
+    );
+}
+
+#[tokio::test]
+async fn exclusive_gateway_selects_matching_flow() {
+    let (_scheduler, procedure, cursor) = setup().await;
+    let selection = match execute(&procedure, "choice", &cursor).await {
+        Next::One(selection @ Executable::Selection(_)) => selection,
+        next => panic!("unexpected {:?}", next),
+    };
+    assert_eq!(
+        selection.execute(cursor.clone()).await.unwrap(),
+        Next::One(procedure.find("always").unwrap())
+    );
+}
+
+#[tokio::test]
+async fn timer_returns_wait() {
+    let (_scheduler, procedure, cursor) = setup().await;
+    let before = Instant::now();
+    match execute(&procedure, "timer", &cursor).await {
+        Next::Wait(executable, deadline) => {
+            assert_eq!(executable, procedure.find("later").unwrap());
+            assert!(deadline >= before + std::time::Duration::from_secs(60));
+        }
+        next => panic!("unexpected {:?}", next),
+    }
+}
+
+#[tokio::test]
+async fn set_wait_returns_wait() {
+    let (_scheduler, procedure, cursor) = setup().await;
+    let end = procedure.find("end").unwrap();
+    let deadline = |next| match next {
+        Next::Wait(executable, deadline) => {
+            assert_eq!(executable, end);
+            deadline
+        }
+        next => panic!("unexpected {:?}", next),
+    };
+
+    let before = Instant::now();
+    let remind = deadline(execute(&procedure, "remind", &cursor).await);
+    assert!(remind >= before + std::time::Duration::from_secs(300));
+    let retry = deadline(execute(&procedure, "retry", &cursor).await);
+    assert!(retry >= before + std::time::Duration::from_millis(1500));
+    assert!(retry < remind);
+    let overdue = deadline(execute(&procedure, "overdue", &cursor).await);
+    assert!(overdue <= Instant::now());
+    let nightly = deadline(execute(&procedure, "nightly", &cursor).await);
+    assert!(nightly <= Instant::now() + std::time::Duration::from_secs(86400));
+}
+
+#[tokio::test]
+async fn set_parallel_and_set_select_resolve_names() {
+    let (_scheduler, procedure, cursor) = setup().await;
+    let end = procedure.find("end").unwrap();
+    assert_eq!(
+        execute(&procedure, "spread", &cursor).await,
+        Next::Parallel(vec![end.clone(), procedure.find("later").unwrap()])
+    );
+    assert_eq!(
+        execute(&procedure, "race", &cursor).await,
+        Next::Select(vec![procedure.find("always").unwrap(), end])
+    );
+}
+
+#[tokio::test]
+async fn set_complete_returns_complete() {
+    let (_scheduler, procedure, cursor) = setup().await;
+    assert_eq!(
+        execute(&procedure, "complete", &cursor).await,
+        Next::Complete
+    );
+}
+
+#[tokio::test]
+async fn set_bubble_returns_bubble() {
+    let (_scheduler, procedure, cursor) = setup().await;
+    assert_eq!(execute(&procedure, "bubble", &cursor).await, Next::Bubble);
+}
+
+#[tokio::test]
+async fn flow_script_routes_or_continues() {
+    let (_scheduler, procedure, cursor) = setup().await;
+    assert_eq!(
+        execute(&procedure, "action", &cursor).await,
+        Next::One(procedure.find("bubble").unwrap())
+    );
+    assert_eq!(execute(&procedure, "plain", &cursor).await, Next::Continue);
+}
+
+#[tokio::test]
+async fn procedure_returns_start_node() {
+    let (_scheduler, procedure, cursor) = setup().await;
+    assert_eq!(
+        Executable::Procedure(Arc::downgrade(&procedure))
+            .execute(cursor.clone())
+            .await
+            .unwrap(),
+        Next::One(procedure.find("start").unwrap())
+    );
+}
+
+#[tokio::test]
+async fn scheduler_follows_script_routing() {
+    let procedure = common::procedure(
+        r#"
+name: route
+start: [start]
+nodes:
+  - name: start
+  - name: jump
+    script: set_one('target')
+  - name: skipped
+    script: set_complete()
+  - name: target
+flows:
+  - { name: to_jump, source: start, target: jump }
+  - { name: to_skipped, source: jump, target: skipped }
+"#,
+    );
+    let (scheduler, cursors) = common::start(vec![procedure.clone()]).await;
+    Scheduler::join(scheduler.clone()).await.unwrap();
+
+    let cursor = cursors[0].read().await;
+    assert!(cursor.is_complete());
+    assert_eq!(*cursor.current(), procedure.find("target").unwrap());
+    assert!(scheduler.read().await.cursors.read().await.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn scheduler_prunes_the_handles_of_finished_cursors() {
+    let quick = common::procedure("{ name: quick, nodes: [{ name: start }] }");
+    let failing =
+        common::procedure("{ name: failing, nodes: [{ name: start, script: \"error('boom')\" }] }");
+    let scheduler = Arc::new(RwLock::new(Scheduler::new()));
+
+    for procedure in std::iter::once(&failing).chain(std::iter::repeat_n(&quick, 10)) {
+        let cursors = Scheduler::start_procedure(scheduler.clone(), vec![procedure.clone()])
+            .await
+            .unwrap();
+        let complete = || async { cursors[0].read().await.is_complete() };
+        common::until(complete, "the cursor completes").await;
+    }
+
+    // the last cursor and maybe the one before it, which may still be exiting
+    assert!(scheduler.read().await.handles.read().await.len() <= 2);
+    // the failure of a pruned cursor is not lost
+    assert!(Scheduler::join(scheduler.clone()).await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn scheduler_runs_parallel_children() {
+    let procedure = common::procedure(
+        r#"
+name: fork
+start: [start]
+nodes:
+  - name: start
+    kind: parallel_gateway
+  - name: left
+  - name: right
+flows:
+  - { name: to_left, source: start, target: left }
+  - { name: to_right, source: start, target: right }
+"#,
+    );
+    let (scheduler, cursors) = common::start(vec![procedure.clone()]).await;
+    Scheduler::join(scheduler.clone()).await.unwrap();
+
+    let cursor = cursors[0].read().await;
+    assert!(cursor.is_complete());
+    let children = cursor.children().await;
+    assert_eq!(children.len(), 2);
+    for (child, name) in children.iter().zip(["left", "right"]) {
+        let child = child.read().await;
+        assert!(child.is_complete());
+        assert_eq!(*child.current(), procedure.find(name).unwrap());
+    }
+}
+
+const JOIN: &str = r#"
+name: join
+start: [start]
+nodes:
+  - name: start
+    kind: parallel_gateway
+  - name: left
+    script: set_state('left', 1); set_continue()
+  - name: right
+    script: set_state('right', 2); set_continue()
+  - name: slow
+    kind: !timer TIMER
+  - name: join
+    kind: !join { count: COUNT }
+  - name: end
+flows:
+  - { name: to_left, source: start, target: left }
+  - { name: to_right, source: start, target: right }
+  - { name: to_slow, source: start, target: slow }
+  - { name: left_join, source: left, target: join }
+  - { name: right_join, source: right, target: join }
+  - { name: slow_join, source: slow, target: join }
+  - { name: to_end, source: join, target: end }
+"#;
+
+async fn run_join(count: &str, timer: &str) -> (Arc<Procedure>, Arc<RwLock<Cursor>>) {
+    let procedure = common::procedure(&JOIN.replace("COUNT", count).replace("TIMER", timer));
+    let (scheduler, cursors) = common::start(vec![procedure.clone()]).await;
+    Scheduler::join(scheduler.clone()).await.unwrap();
+
+    (procedure, cursors[0].clone())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn scheduler_joins_children_and_merges_state() {
+    let (procedure, cursor) = run_join("~", "PT0.01S").await;
+
+    let cursor = cursor.read().await;
+    assert!(cursor.is_complete());
+    assert!(!cursor.has_children().await);
+    assert_eq!(*cursor.current(), procedure.find("end").unwrap());
+    assert!(cursor.context().state.has("left"));
+    assert!(cursor.context().state.has("right"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn scheduler_joins_first_children_and_cancels_the_rest() {
+    let (procedure, cursor) = run_join("2", "PT1H").await;
+
+    let cursor = cursor.read().await;
+    assert!(cursor.is_complete());
+    assert_eq!(*cursor.current(), procedure.find("end").unwrap());
+    assert!(cursor.context().state.has("left"));
+    assert!(cursor.context().state.has("right"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn last_writer_wins_takes_the_child_that_arrived_last() {
+    let procedure = common::procedure(
+        r#"
+name: writers
+start: [start]
+nodes:
+  - name: start
+    kind: parallel_gateway
+  - name: wait
+    kind: !timer PT0.05S
+  - name: late
+    script: set_state('writer', 'late'); set_continue()
+  - name: early
+    script: set_state('writer', 'early'); set_continue()
+  - name: join
+    kind: !join { merge: last_writer_wins }
+  - name: end
+flows:
+  - { name: to_wait, source: start, target: wait }
+  - { name: to_early, source: start, target: early }
+  - { name: to_late, source: wait, target: late }
+  - { name: late_join, source: late, target: join }
+  - { name: early_join, source: early, target: join }
+  - { name: to_end, source: join, target: end }
+"#,
+    );
+    let (scheduler, cursors) = common::start(vec![procedure]).await;
+    Scheduler::join(scheduler.clone()).await.unwrap();
+
+    let cursor = cursors[0].read().await;
+    let writer = Variant::String("late".to_string());
+    assert!(cursor.context().state.get("writer") == Some(&writer));
+}
+
+#[tokio::test]
+async fn inclusive_gateway_without_matching_flow_fails() {
+    let procedure = common::procedure(
+        r#"
+name: inclusive
+start: [start]
+nodes:
+  - name: start
+    kind: inclusive_gateway
+  - name: small
+  - name: large
+flows:
+  - { name: to_small, source: start, target: small, condition: 'false' }
+  - { name: to_large, source: start, target: large, condition: 'false' }
+"#,
+    );
+    let scheduler = Arc::new(RwLock::new(Scheduler::new()));
+    let cursor =
+        Cursor::from_procedure(Arc::downgrade(&scheduler), Arc::downgrade(&procedure)).await;
+
+    let inclusive = match execute(&procedure, "start", &cursor).await {
+        Next::One(inclusive @ Executable::Inclusive(_)) => inclusive,
+        next => panic!("unexpected {:?}", next),
+    };
+    match inclusive.execute(cursor.clone()).await {
+        Err(Error::NoNextNode { procedure, node }) => {
+            assert_eq!(procedure, "inclusive");
+            assert_eq!(node, "start");
+        }
+        result => panic!("unexpected {:?}", result),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn exclusive_gateway_without_matching_flow_fails() {
+    let procedure = common::procedure(
+        r#"
+name: exclusive
+start: [start]
+nodes:
+  - name: start
+    kind: exclusive_gateway
+  - name: never
+flows:
+  - { name: to_never, source: start, target: never, condition: 'false' }
+"#,
+    );
+    let (scheduler, cursors) = common::start(vec![procedure]).await;
+
+    let joined = tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        Scheduler::join(scheduler.clone()),
+    )
+    .await
+    .expect("the cursor did not fail");
+    match joined {
+        Err(Error::NoNextNode { procedure, node }) => {
+            assert_eq!(procedure, "exclusive");
+            assert_eq!(node, "start");
+        }
+        result => panic!("unexpected {:?}", result),
+    }
+    assert!(cursors[0].read().await.is_complete());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn scheduler_joins_only_activated_inclusive_branches() {
+    let procedure = common::procedure(
+        r#"
+name: inclusive
+start: [start]
+nodes:
+  - name: start
+    kind: inclusive_gateway
+  - name: small
+    script: set_state('small', true); set_continue()
+  - name: large
+    script: set_state('large', true); set_continue()
+  - name: manual
+    script: set_state('manual', true); set_continue()
+  - name: join
+    kind: !join {}
+  - name: end
+flows:
+  - { name: to_small, source: start, target: small, condition: 'true' }
+  - { name: to_large, source: start, target: large, condition: '1 < 2' }
+  - { name: to_manual, source: start, target: manual, condition: 'false' }
+  - { name: small_join, source: small, target: join }
+  - { name: large_join, source: large, target: join }
+  - { name: manual_join, source: manual, target: join }
+  - { name: to_end, source: join, target: end }
+"#,
+    );
+    let (scheduler, cursors) = common::start(vec![procedure.clone()]).await;
+    Scheduler::join(scheduler.clone()).await.unwrap();
+
+    let cursor = cursors[0].read().await;
+    assert!(cursor.is_complete());
+    assert_eq!(*cursor.current(), procedure.find("end").unwrap());
+    let state = &cursor.context().state;
+    assert!(state.has("small"));
+    assert!(state.has("large"));
+    assert!(!state.has("manual"));
+}
+
+const EVENTS: &str = r#"
+name: events
+start: [start]
+nodes:
+  - name: start
+  - name: gateway
+    kind: event_gateway
+  - name: paid
+    kind: !message { name: paid }
+  - name: timeout
+    kind: !timer TIMER
+  - name: shipped
+    script: set_state('shipped', true); set_continue()
+  - name: expired
+    script: set_state('expired', true); set_continue()
+flows:
+  - { name: to_gateway, source: start, target: gateway }
+  - { name: to_paid, source: gateway, target: paid }
+  - { name: to_timeout, source: gateway, target: timeout }
+  - { name: to_shipped, source: paid, target: shipped }
+  - { name: to_expired, source: timeout, target: expired }
+"#;
+
+async fn start_events(
+    timer: &str,
+) -> (Arc<RwLock<Scheduler>>, Arc<Procedure>, Arc<RwLock<Cursor>>) {
+    let procedure = common::procedure(&EVENTS.replace("TIMER", timer));
+    let (scheduler, cursors) = common::start(vec![procedure.clone()]).await;
+
+    (scheduler, procedure, cursors[0].clone())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn event_gateway_continues_with_the_message() {
+    let (scheduler, _procedure, cursor) = start_events("PT1H").await;
+    common::wait_for(&scheduler, std::slice::from_ref(&cursor)).await;
+
+    let fired = Scheduler::deliver_message(scheduler.clone(), "paid", "1", State::new())
+        .await
+        .unwrap();
+    assert_eq!(fired, 1);
+    Scheduler::join(scheduler.clone()).await.unwrap();
+
+    let cursor = cursor.read().await;
+    assert!(cursor.is_complete());
+    assert!(cursor.context().state.has("shipped"));
+    assert!(!cursor.context().state.has("expired"));
+    assert!(scheduler.read().await.waits.read().await.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn event_gateway_continues_with_the_timer() {
+    let (scheduler, _procedure, cursor) = start_events("PT0.01S").await;
+    Scheduler::join(scheduler.clone()).await.unwrap();
+
+    let fired = Scheduler::deliver_message(scheduler.clone(), "paid", "1", State::new())
+        .await
+        .unwrap();
+    assert_eq!(fired, 0);
+
+    let cursor = cursor.read().await;
+    assert!(cursor.is_complete());
+    assert!(cursor.context().state.has("expired"));
+    assert!(!cursor.context().state.has("shipped"));
+}
+
+const SLA: &str = r#"
+name: sla
+start: [start]
+nodes:
+  - name: start
+  - name: sla
+    kind: !timer TIMER
+  - name: end
+    script: set_state('done', true); set_continue()
+flows:
+  - { name: to_sla, source: start, target: sla }
+  - { name: to_end, source: sla, target: end }
+"#;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn parked_cursors_do_not_block_other_timers() {
+    let slow = common::procedure(&SLA.replace("TIMER", "PT1H"));
+    let fast = common::procedure(&SLA.replace("TIMER", "PT0.01S"));
+
+    let (scheduler, parked) = common::start(vec![slow.clone(); 100]).await;
+    common::wait_for(&scheduler, &parked).await;
+    let cursors = Scheduler::start_procedure(scheduler.clone(), vec![fast.clone()])
+        .await
+        .unwrap();
+
+    let complete = || async { cursors[0].read().await.is_complete() };
+    common::until(complete, "the fast cursor completes").await;
+
+    assert!(cursors[0].read().await.context().state.has("done"));
+    assert_eq!(scheduler.read().await.timers.len(), parked.len());
+    for cursor in parked {
+        assert!(!cursor.read().await.is_complete());
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn scheduler_forks_one_branch_per_item() {
+    let procedure = common::procedure(
+        r#"
+name: items
+start: [start]
+nodes:
+  - name: start
+    script: |
+      local names = {}
+      for _, item in ipairs({'apple', 'pear', 'plum'}) do
+        table.insert(names, item)
+      end
+      set_parallel(names)
+  - name: apple
+    script: set_state('apple', 1); set_continue()
+  - name: pear
+    script: set_state('pear', 2); set_continue()
+  - name: plum
+    script: set_state('plum', 3); set_continue()
+  - name: join
+    kind: !join {}
+  - name: end
+flows:
+  - { name: apple_join, source: apple, target: join }
+  - { name: pear_join, source: pear, target: join }
+  - { name: plum_join, source: plum, target: join }
+  - { name: to_end, source: join, target: end }
+"#,
+    );
+    let (scheduler, cursors) = common::start(vec![procedure.clone()]).await;
+    Scheduler::join(scheduler.clone()).await.unwrap();
+
+    let cursor = cursors[0].read().await;
+    assert!(cursor.is_complete());
+    assert_eq!(*cursor.current(), procedure.find("end").unwrap());
+    for item in ["apple", "pear", "plum"] {
+        assert!(cursor.context().state.has(item));
+    }
+}
+
+const ORDER: &str = r#"
+name: order
+start: [start]
+nodes:
+  - name: start
+    script: set_state('order_id', ORDER); set_continue()
+  - name: paid
+    kind: !message { name: paid, correlation: order_id }
+  - name: end
+flows:
+  - { name: to_paid, source: start, target: paid }
+  - { name: to_end, source: paid, target: end }
+"#;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn messages_are_delivered_by_correlation_key() {
+    let procedure = |order: &str| common::procedure(&ORDER.replace("ORDER", order));
+    let procedures = vec![procedure("1"), procedure("2")];
+    let (scheduler, cursors) = common::start(procedures.clone()).await;
+    common::wait_for(&scheduler, &cursors).await;
+
+    let mut payload = State::new();
+    payload.set("amount".to_string(), Variant::Integer(42));
+    let delivered = Scheduler::deliver_message(scheduler.clone(), "paid", "2", payload)
+        .await
+        .unwrap();
+    assert_eq!(delivered, 1);
+    let missed = Scheduler::deliver_message(scheduler.clone(), "shipped", "1", State::new())
+        .await
+        .unwrap();
+    assert_eq!(missed, 0);
+
+    let handle = scheduler
+        .read()
+        .await
+        .handles
+        .write()
+        .await
+        .remove(cursors[1].read().await.id())
+        .unwrap();
+    handle.await.unwrap().unwrap();
+
+    let second = cursors[1].read().await;
+    assert!(second.is_complete());
+    assert_eq!(*second.current(), procedures[1].find("end").unwrap());
+    assert!(second.context().state.get("amount") == Some(&Variant::Integer(42)));
+    let first = cursors[0].read().await;
+    assert!(!first.is_complete());
+    assert!(!first.context().state.has("amount"));
+}
+
+const DIRECT: &str = r#"
+name: direct
+start: [start]
+nodes:
+  - name: start
+  - name: updated
+    kind: !signal { name: prices }
+  - name: end
+flows:
+  - { name: to_updated, source: start, target: updated }
+  - { name: to_end, source: updated, target: end }
+"#;
+
+const RACED: &str = r#"
+name: raced
+start: [start]
+nodes:
+  - name: start
+    kind: event_gateway
+  - name: updated
+    kind: !signal { name: prices }
+  - name: idle
+    kind: !timer PT1H
+  - name: end
+flows:
+  - { name: to_updated, source: start, target: updated }
+  - { name: to_idle, source: start, target: idle }
+  - { name: updated_end, source: updated, target: end }
+  - { name: idle_end, source: idle, target: end }
+"#;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn signals_wake_every_waiting_cursor() {
+    let direct = common::procedure(DIRECT);
+    let raced = common::procedure(RACED);
+    let (scheduler, cursors) = common::start(vec![direct, raced.clone(), raced]).await;
+    common::wait_for(&scheduler, &cursors).await;
+
+    let mut payload = State::new();
+    payload.set("version".to_string(), Variant::Integer(7));
+    let woken = Scheduler::broadcast_signal(scheduler.clone(), "prices", payload)
+        .await
+        .unwrap();
+    assert_eq!(woken, 3);
+    Scheduler::join(scheduler.clone()).await.unwrap();
+
+    for cursor in cursors {
+        let cursor = cursor.read().await;
+        assert!(cursor.is_complete());
+        assert!(cursor.context().state.get("version") == Some(&Variant::Integer(7)));
+    }
+    assert!(scheduler.read().await.timers.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn signals_skip_cursors_that_can_not_be_woken() {
+    let raced = common::procedure(RACED);
+    let (scheduler, cursors) = common::start(vec![raced.clone(), raced]).await;
+    common::wait_for(&scheduler, &cursors).await;
+
+    // the task of the first cursor is gone, with the receiver of its wake ups
+    let id = cursors[0].read().await.id().to_string();
+    let handle = scheduler
+        .read()
+        .await
+        .handles
+        .write()
+        .await
+        .remove(&id)
+        .unwrap();
+    handle.abort();
+    let _ = handle.await;
+
+    let woken = Scheduler::broadcast_signal(scheduler.clone(), "prices", State::new())
+        .await
+        .unwrap();
+    assert_eq!(woken, 1);
+    Scheduler::join(scheduler.clone()).await.unwrap();
+    assert!(cursors[1].read().await.is_complete());
+}
+
+const CANCEL: &str = r#"
+name: cancel
+start: [start]
+nodes:
+  - name: start
+    kind: parallel_gateway
+  - name: reserve
+    kind: !timer PT1H
+    on_cancel: set_state('released', true)
+  - name: charge
+    kind: !timer PT1H
+    on_cancel: error('refund failed')
+  - name: end
+flows:
+  - { name: to_reserve, source: start, target: reserve }
+  - { name: to_charge, source: start, target: charge }
+  - { name: reserved, source: reserve, target: end }
+  - { name: charged, source: charge, target: end }
+"#;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cancel_cursor_cancels_the_tree_and_runs_cleanup() {
+    let procedure = common::procedure(CANCEL);
+    let (scheduler, cursors) = common::start(vec![procedure.clone()]).await;
+    let root = cursors[0].clone();
+    let forked = || async { root.read().await.children().await.len() == 2 };
+    common::until(forked, "the children are forked").await;
+    let children = root.read().await.children().await;
+    common::wait_for(&scheduler, &children).await;
+
+    let id = root.read().await.id().to_string();
+    Scheduler::cancel_cursor(scheduler.clone(), &id, "order withdrawn")
+        .await
+        .unwrap();
+    Scheduler::join(scheduler.clone()).await.unwrap();
+
+    let canceled = History::Canceled {
+        reason: "order withdrawn".to_string(),
+    };
+    let root = root.read().await;
+    assert!(root.is_complete());
+    assert_eq!(root.history().to_vec(), vec![canceled.clone()]);
+    for child in children {
+        let child = child.read().await;
+        assert!(child.is_complete());
+        assert_eq!(child.history().last(), Some(&canceled));
+        if *child.current() == procedure.find("reserve").unwrap() {
+            assert!(child.context().state.has("released"));
+        } else {
+            assert!(matches!(
+                &child.history()[0],
+                History::CancelScriptFailed { node, .. } if node == "charge"
+            ));
+        }
+    }
+    assert!(scheduler.read().await.timers.is_empty());
+    assert!(Scheduler::cancel_cursor(scheduler.clone(), &id, "again")
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn executable_keys_resolve_and_survive_the_definition() {
+    let (_scheduler, procedure, _cursor) = setup().await;
+    let executables = [
+        Executable::Procedure(Arc::downgrade(&procedure)),
+        procedure.find("choice").unwrap(),
+        procedure.find("later").unwrap(),
+        Executable::Selection(vec![
+            Arc::downgrade(&procedure.flows["never"]),
+            Arc::downgrade(&procedure.flows["always"]),
+        ]),
+    ];
+
+    for executable in &executables {
+        let key = executable.key().unwrap();
+        let json = serde_json::to_string(&key).unwrap();
+        let key: ExecutableKey = serde_json::from_str(&json).unwrap();
+        assert_eq!(procedure.resolve(&key).unwrap(), *executable);
+    }
+    let key = procedure.find("choice").unwrap().key().unwrap();
+    assert_eq!(key.to_string(), "next@0/node:choice");
+
+    // a newer version does not resolve keys of the older one
+    let mut definition = ProcedureDefinition::from_yaml(PROCEDURE).unwrap();
+    definition.version = 1;
+    let newer = Arc::new(Procedure::from_definition(&definition).unwrap());
+    assert!(newer.resolve(&key).is_err());
+
+    // comparing executables of a dropped definition does not panic
+    drop(procedure);
+    assert_eq!(executables[1], executables[1].clone());
+    assert_ne!(executables[1], executables[2]);
+    assert!(executables[1].key().is_err());
+}
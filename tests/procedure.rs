@@ -0,0 +1,134 @@
+use donut::{
+    definition::{FlowDefinition, NodeDefinition, ProcedureDefinition},
+    error::Error,
+    procedure::Procedure,
+};
+
+const ORDER: &str = r#"
+name: order
+version: 2
+start: [start]
+nodes:
+  - name: start
+    script: set_continue()
+  - name: choice
+    kind: exclusive_gateway
+  - name: paid
+    kind: !message { name: paid, correlation: order_id }
+  - name: reminder
+    kind: !timer PT5M
+  - name: join
+    kind: !join { count: 1, merge: last_writer_wins }
+  - name: end
+    on_cancel: x = 1
+flows:
+  - { name: to_choice, source: start, target: choice }
+  - { name: to_paid, source: choice, target: paid, condition: 'total > 0' }
+  - { name: to_reminder, source: choice, target: reminder, script: x = 2 }
+  - { name: paid_join, source: paid, target: join }
+  - { name: reminder_join, source: reminder, target: join }
+  - { name: to_end, source: join, target: end }
+"#;
+
+fn build(source: &str) -> Result<Procedure, Error> {
+    Procedure::from_definition(&ProcedureDefinition::from_yaml(source).unwrap())
+}
+
+#[test]
+fn yaml_and_json_round_trip() {
+    let definition = ProcedureDefinition::from_yaml(ORDER).unwrap();
+    let yaml = definition.to_yaml().unwrap();
+    let json = definition.to_json().unwrap();
+
+    let from_yaml = ProcedureDefinition::from_yaml(&yaml).unwrap();
+    let from_json = ProcedureDefinition::from_json(&json).unwrap();
+    assert_eq!(from_yaml.to_yaml().unwrap(), yaml);
+    assert_eq!(from_json.to_yaml().unwrap(), yaml);
+    assert_eq!(from_json.to_json().unwrap(), json);
+
+    let procedure = Procedure::from_definition(&from_json).unwrap();
+    assert_eq!(procedure.version, 2);
+    assert_eq!(procedure.nodes.len(), 6);
+    assert_eq!(procedure.flows.len(), 6);
+    let choice = &procedure.nodes["choice"];
+    assert_eq!(choice.incomings.len(), 1);
+    assert_eq!(choice.outgoings.len(), 2);
+    let to_paid = &procedure.flows["to_paid"];
+    assert_eq!(to_paid.source().upgrade().unwrap().name, "choice");
+    assert_eq!(to_paid.target().upgrade().unwrap().name, "paid");
+    assert_eq!(to_paid.condition, "total > 0");
+}
+
+#[test]
+fn duplicate_names_are_rejected() {
+    let node = build(
+        r#"
+name: twice
+nodes: [{ name: a }, { name: a }]
+"#,
+    );
+    assert!(matches!(node, Err(Error::DuplicateName { name, .. }) if name == "a"));
+
+    // nodes and flows share one namespace
+    let flow = build(
+        r#"
+name: shared
+nodes: [{ name: a }, { name: b }]
+flows: [{ name: a, source: a, target: b }]
+"#,
+    );
+    assert!(matches!(flow, Err(Error::DuplicateName { name, .. }) if name == "a"));
+}
+
+#[test]
+fn dangling_references_are_rejected() {
+    let result = build(
+        r#"
+name: dangling
+nodes: [{ name: a }]
+flows: [{ name: to_b, source: a, target: b }]
+"#,
+    );
+    assert!(matches!(
+        result,
+        Err(Error::DanglingReference { flow, node, .. }) if flow == "to_b" && node == "b"
+    ));
+
+    let start = build(
+        r#"
+name: start
+start: [missing]
+nodes: [{ name: a }]
+"#,
+    );
+    assert!(matches!(start, Err(Error::NotFound { name, .. }) if name == "missing"));
+}
+
+#[test]
+fn large_graphs_build_without_deep_recursion() {
+    let count = 100_000;
+    let name = |index: usize| format!("n{}", index);
+    let definition = ProcedureDefinition {
+        name: "chain".to_string(),
+        nodes: (0..count)
+            .map(|index| NodeDefinition {
+                name: name(index),
+                ..Default::default()
+            })
+            .collect(),
+        flows: (1..count)
+            .map(|index| FlowDefinition {
+                name: format!("f{}", index),
+                source: name(index - 1),
+                target: name(index),
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    };
+
+    let procedure = Procedure::from_definition(&definition).unwrap();
+    assert_eq!(procedure.nodes.len(), count);
+    let last = procedure.flows["f99999"].target().upgrade().unwrap();
+    assert_eq!(last.name, "n99999");
+}
@@ -0,0 +1,122 @@
+use std::sync::Arc;
+
+use donut::{
+    cursor::Cursor, definition::ProcedureDefinition, error::Error, procedure::Procedure,
+    scheduler::Scheduler, script::Script, state::Variant,
+};
+use tokio::sync::RwLock;
+
+const CONDITIONS: &str = r#"
+name: conditions
+start: [start]
+nodes: [{ name: start }, { name: end }]
+flows:
+  - { name: large, source: start, target: end, condition: "get_state('total') > 100" }
+  - { name: small, source: start, target: end, condition: "state.total <= 100" }
+  - { name: fallback, source: start, target: end, condition: "total == 250 and paid" }
+  - { name: number, source: start, target: end, condition: "total" }
+  - { name: always, source: start, target: end }
+"#;
+
+async fn setup(source: &str) -> (Arc<RwLock<Scheduler>>, Arc<Procedure>, Arc<RwLock<Cursor>>) {
+    let definition = ProcedureDefinition::from_yaml(source).unwrap();
+    let procedure = Arc::new(Procedure::from_definition(&definition).unwrap());
+    let scheduler = Arc::new(RwLock::new(Scheduler::new()));
+    let cursor =
+        Cursor::from_procedure(Arc::downgrade(&scheduler), Arc::downgrade(&procedure)).await;
+    (scheduler, procedure, cursor)
+}
+
+async fn set(cursor: &Arc<RwLock<Cursor>>, key: &str, value: Variant) {
+    let mut cursor = cursor.write().await;
+    cursor.context_mut().state.set(key.to_string(), value);
+}
+
+async fn check(
+    procedure: &Procedure,
+    name: &str,
+    cursor: &Arc<RwLock<Cursor>>,
+) -> Result<bool, Error> {
+    procedure.flows[name].check_condition(cursor.clone()).await
+}
+
+#[tokio::test]
+async fn conditions_are_evaluated_over_the_state() {
+    let (_scheduler, procedure, cursor) = setup(CONDITIONS).await;
+    set(&cursor, "total", Variant::Integer(250)).await;
+    set(&cursor, "paid", Variant::Boolean(true)).await;
+
+    assert!(check(&procedure, "large", &cursor).await.unwrap());
+    assert!(!check(&procedure, "small", &cursor).await.unwrap());
+    assert!(check(&procedure, "always", &cursor).await.unwrap());
+
+    set(&cursor, "total", Variant::Integer(50)).await;
+    assert!(!check(&procedure, "large", &cursor).await.unwrap());
+    assert!(check(&procedure, "small", &cursor).await.unwrap());
+}
+
+#[tokio::test]
+async fn undefined_globals_fall_back_to_the_state() {
+    let (_scheduler, procedure, cursor) = setup(CONDITIONS).await;
+    set(&cursor, "total", Variant::Integer(250)).await;
+    set(&cursor, "paid", Variant::Boolean(true)).await;
+    assert!(check(&procedure, "fallback", &cursor).await.unwrap());
+
+    set(&cursor, "paid", Variant::Boolean(false)).await;
+    assert!(!check(&procedure, "fallback", &cursor).await.unwrap());
+}
+
+#[tokio::test]
+async fn non_boolean_conditions_fail() {
+    let (_scheduler, procedure, cursor) = setup(CONDITIONS).await;
+    set(&cursor, "total", Variant::Integer(250)).await;
+    match check(&procedure, "number", &cursor).await {
+        Err(Error::ScriptFailed { reason }) => assert!(reason.contains("instead of boolean")),
+        result => panic!("unexpected {:?}", result),
+    }
+}
+
+#[tokio::test]
+async fn state_writes_are_kept_when_the_script_succeeds() {
+    let (_scheduler, _procedure, cursor) = setup(CONDITIONS).await;
+    set(&cursor, "total", Variant::Integer(250)).await;
+    set(&cursor, "draft", Variant::Boolean(true)).await;
+
+    let script = Script::new(cursor.clone());
+    script
+        .execute(
+            "set_state('discount', get_state('total') // 10)
+             set_state('had_draft', has_state('draft'))
+             set_state('removed', remove_state('draft'))
+             set_state('still_draft', has_state('draft'))
+             set_state('missing', remove_state('unknown') == nil)",
+        )
+        .await
+        .unwrap();
+
+    let cursor = cursor.read().await;
+    let state = &cursor.context().state;
+    assert_eq!(state.get("discount"), Some(&Variant::Integer(25)));
+    assert_eq!(state.get("had_draft"), Some(&Variant::Boolean(true)));
+    assert_eq!(state.get("removed"), Some(&Variant::Boolean(true)));
+    assert_eq!(state.get("still_draft"), Some(&Variant::Boolean(false)));
+    assert_eq!(state.get("missing"), Some(&Variant::Boolean(true)));
+    assert!(!state.has("draft"));
+}
+
+#[tokio::test]
+async fn state_writes_are_discarded_when_the_script_fails() {
+    let (_scheduler, _procedure, cursor) = setup(CONDITIONS).await;
+    set(&cursor, "total", Variant::Integer(250)).await;
+
+    let script = Script::new(cursor.clone());
+    let result = script
+        .execute("set_state('total', 0); remove_state('total'); set_state('x', 1); error('boom')")
+        .await;
+    assert!(matches!(result, Err(Error::ScriptFailed { .. })));
+
+    let cursor = cursor.read().await;
+    let state = &cursor.context().state;
+    assert_eq!(state.get("total"), Some(&Variant::Integer(250)));
+    assert!(!state.has("x"));
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use donut::{
+    error::Error,
+    state::{State, Variant},
+};
+use serde_json::{json, Value};
+
+fn order() -> State {
+    let mut line = HashMap::new();
+    line.insert("sku".to_string(), Variant::String("A-1".to_string()));
+    line.insert("quantity".to_string(), Variant::Integer(2));
+    line.insert("price".to_string(), Variant::Float(10.0));
+
+    let mut state = State::new();
+    state.set("id".to_string(), Variant::Integer(42));
+    state.set("paid".to_string(), Variant::Boolean(false));
+    state.set("note".to_string(), Variant::Null);
+    state.set(
+        "lines".to_string(),
+        Variant::Array(vec![Variant::Object(line), Variant::Float(0.5)]),
+    );
+    state
+}
+
+#[test]
+fn json_round_trip_keeps_integers_and_floats_apart() {
+    let state = order();
+    let text = serde_json::to_string(&state).unwrap();
+    assert_eq!(
+        text,
+        r#"{"id":42,"lines":[{"price":10.0,"quantity":2,"sku":"A-1"},0.5],"note":null,"paid":false}"#
+    );
+
+    let parsed: State = serde_json::from_str(&text).unwrap();
+    assert_eq!(parsed, state);
+}
+
+#[test]
+fn msgpack_round_trip() {
+    let state = order();
+    let bytes = state.to_msgpack().unwrap();
+    assert_eq!(State::from_msgpack(&bytes).unwrap(), state);
+
+    let variant = Variant::Array(vec![Variant::Integer(-1), Variant::Float(-1.0)]);
+    let bytes = variant.to_msgpack().unwrap();
+    assert_eq!(Variant::from_msgpack(&bytes).unwrap(), variant);
+    assert!(matches!(
+        State::from_msgpack(&bytes),
+        Err(Error::Encoding { .. })
+    ));
+}
+
+#[test]
+fn converts_from_and_into_json_values() {
+    let value = json!({
+        "id": 42,
+        "lines": [{ "sku": "A-1", "quantity": 2, "price": 10.0 }, 0.5],
+        "note": null,
+        "paid": false,
+    });
+
+    let state = State::try_from(value.clone()).unwrap();
+    assert_eq!(state, order());
+    assert_eq!(Value::from(state), value);
+
+    assert_eq!(Variant::from(json!(1.5)), Variant::Float(1.5));
+    assert_eq!(Value::from(Variant::Float(f64::NAN)), Value::Null);
+    assert!(matches!(
+        State::try_from(json!([1, 2])),
+        Err(Error::Encoding { .. })
+    ));
+}
+
+fn rich() -> Variant {
+    let mut values = HashMap::new();
+    values.insert(
+        "due".to_string(),
+        Variant::DateTime("2026-10-18T09:30:00.25Z".parse().unwrap()),
+    );
+    values.insert(
+        "grace".to_string(),
+        Variant::Duration(chrono::TimeDelta::milliseconds(-90_500)),
+    );
+    values.insert(
+        "total".to_string(),
+        Variant::Decimal("12.50".parse().unwrap()),
+    );
+    values.insert("digest".to_string(), Variant::Bytes(vec![0, 159, 255]));
+    Variant::Object(values)
+}
+
+#[test]
+fn rich_values_round_trip_as_tagged_strings() {
+    let variant = rich();
+    let text = serde_json::to_string(&variant).unwrap();
+    assert_eq!(
+        text,
+        r#"{"digest":{"$bytes":"AJ//"},"due":{"$datetime":"2026-10-18T09:30:00.250Z"},"grace":{"$duration":"-PT90.5S"},"total":{"$decimal":"12.50"}}"#
+    );
+    assert_eq!(serde_json::from_str::<Variant>(&text).unwrap(), variant);
+
+    let value = Value::from(variant.clone());
+    assert_eq!(value["total"], json!({ "$decimal": "12.50" }));
+    assert_eq!(Variant::from(value), variant);
+
+    // bytes are native in msgpack
+    let bytes = variant.to_msgpack().unwrap();
+    assert_eq!(Variant::from_msgpack(&bytes).unwrap(), variant);
+    let bytes = Variant::Bytes(vec![1, 2]).to_msgpack().unwrap();
+    assert_eq!(bytes, vec![0xc4, 2, 1, 2]);
+
+    // a tag with an unreadable value or a second entry is a plain object
+    for value in [
+        json!({ "$decimal": "twelve" }),
+        json!({ "$decimal": "1", "other": 2 }),
+    ] {
+        assert!(matches!(Variant::from(value), Variant::Object(_)));
+    }
+}
+
+#[test]
+fn objects_with_tag_like_keys_round_trip() {
+    let object = |key: &str| {
+        Variant::Object(
+            [(key.to_string(), Variant::String("12.5".to_string()))]
+                .into_iter()
+                .collect(),
+        )
+    };
+    for key in ["$decimal", "$$decimal", "$other", "$", "plain"] {
+        let variant = object(key);
+        let text = serde_json::to_string(&variant).unwrap();
+        assert_eq!(serde_json::from_str::<Variant>(&text).unwrap(), variant);
+        assert_eq!(Variant::from(Value::from(variant.clone())), variant);
+        let bytes = variant.to_msgpack().unwrap();
+        assert_eq!(Variant::from_msgpack(&bytes).unwrap(), variant);
+    }
+    let text = serde_json::to_string(&object("$decimal")).unwrap();
+    assert_eq!(text, r#"{"$$decimal":"12.5"}"#);
+
+    // state keys too
+    let mut state = State::new();
+    state.set("$decimal".to_string(), Variant::String("12.5".to_string()));
+    let text = serde_json::to_string(&state).unwrap();
+    assert_eq!(serde_json::from_str::<State>(&text).unwrap(), state);
+    assert_eq!(State::try_from(Value::from(state.clone())).unwrap(), state);
+}
@@ -0,0 +1,299 @@
+mod common;
+
+use std::{fs::OpenOptions, io::Write, sync::Arc, time::Duration};
+
+use donut::{
+    base::{Element, ExecutableKey, ProcedureKey},
+    cursor::Cursor,
+    error::Error,
+    event::Pending,
+    scheduler::Scheduler,
+    state::{State, Variant},
+    storage::{CursorRecord, FileStorage, MemoryStorage, SqliteStorage, Storage},
+};
+use tokio::{sync::RwLock, time::Instant};
+use uuid::Uuid;
+
+const ORDER: &str = r#"
+name: order
+start: [start]
+nodes:
+  - name: start
+    script: set_state('order_id', 'A-1'); set_state('lines', {1, 2, 3}); set_continue()
+  - name: paid
+    kind: !message { name: paid, correlation: order_id }
+  - name: end
+    script: set_state('done', true); set_continue()
+flows:
+  - { name: to_paid, source: start, target: paid }
+  - { name: to_end, source: paid, target: end }
+"#;
+
+const FORK: &str = r#"
+name: fork
+start: [start]
+nodes:
+  - name: start
+    kind: parallel_gateway
+  - name: left
+    kind: !timer PT1H
+  - name: right
+    kind: !timer PT1H
+  - name: join
+    kind: !join {}
+  - name: end
+flows:
+  - { name: to_left, source: start, target: left }
+  - { name: to_right, source: start, target: right }
+  - { name: left_join, source: left, target: join }
+  - { name: right_join, source: right, target: join }
+  - { name: to_end, source: join, target: end }
+"#;
+
+const PARKED: &str = r#"
+name: parked
+start: [start]
+nodes:
+  - name: start
+    script: set_state('runs', (get_state('runs') or 0) + 1); set_wait('end', 2)
+  - name: end
+    script: set_state('done', true); set_continue()
+"#;
+
+fn temp_path() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("donut-{}", Uuid::now_v7()))
+}
+
+// stop every cursor task without letting it save anything, like a crash
+async fn crash(scheduler: Arc<RwLock<Scheduler>>) {
+    let handles: Vec<_> = {
+        let scheduler = scheduler.read().await;
+        let mut handles = scheduler.handles.write().await;
+        handles.drain().map(|(_, handle)| handle).collect()
+    };
+    for handle in handles {
+        handle.abort();
+        let _ = handle.await;
+    }
+}
+
+fn paid() -> ExecutableKey {
+    ExecutableKey {
+        procedure: ProcedureKey {
+            name: "order".to_string(),
+            version: 1,
+        },
+        element: Element::Node("paid".to_string()),
+    }
+}
+
+fn record(id: &str, parent: Option<&str>, value: i64) -> CursorRecord {
+    let mut state = State::new();
+    state.set("value".to_string(), Variant::Integer(value));
+    state.set("ratio".to_string(), Variant::Float(1.0));
+    CursorRecord {
+        id: id.to_string(),
+        parent: parent.map(str::to_string),
+        current: paid(),
+        state,
+        is_complete: false,
+        pending: None,
+    }
+}
+
+#[test]
+fn storages_keep_the_latest_record_per_cursor() {
+    let path = temp_path();
+    let storages: Vec<Box<dyn Storage>> = vec![
+        Box::new(MemoryStorage::new()),
+        Box::new(FileStorage::open(&path).unwrap()),
+        Box::new(SqliteStorage::open_in_memory().unwrap()),
+    ];
+
+    for storage in storages {
+        storage.save(&record("root", None, 1)).unwrap();
+        storage.save(&record("child", Some("root"), 2)).unwrap();
+        storage.save(&record("root", None, 3)).unwrap();
+        storage.save(&record("gone", None, 4)).unwrap();
+        storage.remove("gone").unwrap();
+
+        let mut records = storage.load().unwrap();
+        records.sort_by(|a, b| a.id.cmp(&b.id));
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].id, "child");
+        assert_eq!(records[0].parent.as_deref(), Some("root"));
+        assert_eq!(records[1].id, "root");
+        assert!(records[1].state.get("value") == Some(&Variant::Integer(3)));
+        assert!(records[1].state.get("ratio") == Some(&Variant::Float(1.0)));
+        assert_eq!(records[1].current, paid());
+    }
+
+    // a reopened file replays what was appended before
+    let reopened = FileStorage::open(&path).unwrap();
+    assert_eq!(reopened.load().unwrap().len(), 2);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn file_storage_drops_a_torn_last_line() {
+    let path = temp_path();
+    let storage = FileStorage::open(&path).unwrap();
+    storage.save(&record("root", None, 1)).unwrap();
+    drop(storage);
+
+    // a crash in the middle of an append
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(br#"{"save":{"id":"torn","par"#).unwrap();
+    drop(file);
+
+    let storage = FileStorage::open(&path).unwrap();
+    storage.save(&record("child", Some("root"), 2)).unwrap();
+    let reopened = FileStorage::open(&path).unwrap();
+    let mut records = reopened.load().unwrap();
+    records.sort_by(|a, b| a.id.cmp(&b.id));
+    let ids: Vec<_> = records.iter().map(|record| record.id.as_str()).collect();
+    assert_eq!(ids, ["child", "root"]);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn file_storage_rejects_a_corrupt_line() {
+    let path = temp_path();
+    let storage = FileStorage::open(&path).unwrap();
+    storage.save(&record("one", None, 1)).unwrap();
+    storage.save(&record("two", None, 2)).unwrap();
+    storage.remove("two").unwrap();
+    drop(storage);
+
+    // break the removal in the middle of the file
+    let content = std::fs::read_to_string(&path).unwrap();
+    let content = content.replace(r#"{"remove":"two"}"#, r#"{"remove":"tw"#);
+    std::fs::write(&path, content).unwrap();
+
+    let storage = FileStorage::open(&path).unwrap();
+    storage.save(&record("three", None, 3)).unwrap();
+    match storage.load() {
+        Err(Error::Storage { reason }) => assert!(reason.starts_with("line 3:")),
+        Err(error) => panic!("unexpected {:?}", error),
+        Ok(records) => panic!("loaded {} records", records.len()),
+    }
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn recover_resumes_a_waiting_cursor_after_a_crash() {
+    let path = temp_path();
+    let storage = Arc::new(FileStorage::open(&path).unwrap());
+    let scheduler = Arc::new(RwLock::new(Scheduler::with_storage(storage.clone())));
+    let cursors = Scheduler::start_procedure(scheduler.clone(), vec![common::procedure(ORDER)])
+        .await
+        .unwrap();
+    let id = cursors[0].read().await.id().to_string();
+    common::wait_for(&scheduler, &cursors).await;
+    crash(scheduler).await;
+
+    // a restarted server loads the definitions and its storage again
+    let storage = Arc::new(FileStorage::open(&path).unwrap());
+    let scheduler = Arc::new(RwLock::new(Scheduler::with_storage(storage.clone())));
+    let order = common::procedure(ORDER);
+    let cursors = Scheduler::recover(scheduler.clone(), vec![order.clone()])
+        .await
+        .unwrap();
+    assert_eq!(cursors.len(), 1);
+    assert_eq!(cursors[0].read().await.id(), id);
+    common::wait_for(&scheduler, &cursors).await;
+
+    let delivered = Scheduler::deliver_message(scheduler.clone(), "paid", "A-1", State::new())
+        .await
+        .unwrap();
+    assert_eq!(delivered, 1);
+    Scheduler::join(scheduler.clone()).await.unwrap();
+
+    let cursor = cursors[0].read().await;
+    assert!(cursor.is_complete());
+    assert_eq!(*cursor.current(), order.find("end").unwrap());
+    let lines = Variant::Array(vec![
+        Variant::Integer(1),
+        Variant::Integer(2),
+        Variant::Integer(3),
+    ]);
+    assert!(cursor.context().state.get("lines") == Some(&lines));
+    assert!(cursor.context().state.has("done"));
+
+    let records = storage.load().unwrap();
+    assert_eq!(records.len(), 1);
+    assert!(records[0].is_complete);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn recover_rebuilds_the_cursor_tree() {
+    let storage = Arc::new(SqliteStorage::open_in_memory().unwrap());
+    let scheduler = Arc::new(RwLock::new(Scheduler::with_storage(storage.clone())));
+    let cursors = Scheduler::start_procedure(scheduler.clone(), vec![common::procedure(FORK)])
+        .await
+        .unwrap();
+    let forked = || async { cursors[0].read().await.children().await.len() == 2 };
+    common::until(forked, "the children are forked").await;
+    let children = cursors[0].read().await.children().await;
+    common::wait_for(&scheduler, &children).await;
+    crash(scheduler).await;
+
+    let scheduler = Arc::new(RwLock::new(Scheduler::with_storage(storage)));
+    let fork = common::procedure(FORK);
+    let roots = Scheduler::recover(scheduler.clone(), vec![fork.clone()])
+        .await
+        .unwrap();
+    assert_eq!(roots.len(), 1);
+
+    let recovered = roots[0].read().await.children().await;
+    assert_eq!(recovered.len(), 2);
+    let mut positions = vec![];
+    common::wait_for(&scheduler, &recovered).await;
+    for child in &recovered {
+        let child = child.read().await;
+        assert!(Arc::ptr_eq(&child.parent().unwrap().unwrap(), &roots[0]));
+        positions.push(child.current().clone());
+    }
+    assert!(positions.contains(&fork.find("left").unwrap()));
+    assert!(positions.contains(&fork.find("right").unwrap()));
+}
+
+// the deadline a cursor is parked until
+async fn parked_until(cursor: &Arc<RwLock<Cursor>>) -> Instant {
+    match cursor.read().await.pending() {
+        Some(Pending::Resume(deadline, _)) => *deadline,
+        _ => panic!("the cursor is not parked"),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn recover_keeps_the_deadline_of_a_parked_cursor() {
+    let storage = Arc::new(SqliteStorage::open_in_memory().unwrap());
+    let scheduler = Arc::new(RwLock::new(Scheduler::with_storage(storage.clone())));
+    let cursors = Scheduler::start_procedure(scheduler.clone(), vec![common::procedure(PARKED)])
+        .await
+        .unwrap();
+    common::wait_for(&scheduler, &cursors).await;
+    let deadline = parked_until(&cursors[0]).await;
+    crash(scheduler).await;
+
+    // the server is down for a while
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let scheduler = Arc::new(RwLock::new(Scheduler::with_storage(storage.clone())));
+    let cursors = Scheduler::recover(scheduler.clone(), vec![common::procedure(PARKED)])
+        .await
+        .unwrap();
+    common::wait_for(&scheduler, &cursors).await;
+
+    // the script is not run again and the wait does not start over
+    let recovered = parked_until(&cursors[0]).await;
+    assert!(recovered.max(deadline) - recovered.min(deadline) < Duration::from_millis(500));
+    assert!(cursors[0].read().await.context().state.get("runs") == Some(&Variant::Integer(1)));
+
+    Scheduler::join(scheduler.clone()).await.unwrap();
+    let cursor = cursors[0].read().await;
+    assert!(cursor.is_complete());
+    assert!(cursor.context().state.has("done"));
+    assert!(Instant::now() < deadline + Duration::from_millis(500));
+}
@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use chrono::{Datelike, Utc, Weekday};
+use donut::timer::{self, Timers};
+use tokio::time::Instant;
+
+const DAY: Duration = Duration::from_secs(86400);
+
+#[tokio::test(start_paused = true)]
+async fn far_deadlines_are_rearmed_until_due() {
+    let timers = Timers::new();
+    let start = Instant::now();
+    timers.insert("far", start + 1000 * DAY, "far");
+    timers.insert("near", start + DAY, "near");
+
+    assert_eq!(timers.expired().await, ("near".to_string(), "near"));
+    assert!(timers.contains("far"));
+
+    assert_eq!(timers.expired().await, ("far".to_string(), "far"));
+    assert!(Instant::now() >= start + 1000 * DAY);
+    assert!(timers.is_empty());
+    assert!(!timers.contains("far"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn canceled_far_deadlines_do_not_fire() {
+    let timers = Timers::new();
+    timers.insert("far", Instant::now() + 5000 * DAY, 1);
+    timers.cancel("far");
+    timers.insert("next", Instant::now() + 6000 * DAY, 2);
+
+    assert_eq!(timers.expired().await, ("next".to_string(), 2));
+    assert!(timers.is_empty());
+}
+
+// the weekday a deadline of `next_cron` falls on
+fn weekday(deadline: Instant) -> Weekday {
+    let delay = deadline - Instant::now();
+    // the deadline is on the minute, half a minute keeps the clock drift out
+    (Utc::now() + delay + Duration::from_secs(30))
+        .date_naive()
+        .weekday()
+}
+
+#[test]
+fn unix_cron_counts_weekdays_from_sunday() {
+    let sunday = timer::next_cron("0 9 * * 0").unwrap();
+    assert_eq!(weekday(sunday), Weekday::Sun);
+    assert_eq!(
+        weekday(timer::next_cron("0 9 * * 7").unwrap()),
+        Weekday::Sun
+    );
+    assert_eq!(
+        weekday(timer::next_cron("0 0 * * 6").unwrap()),
+        Weekday::Sat
+    );
+
+    // only the weekdays, whichever day it is now
+    for day in 0..7 {
+        let expression = format!("0 0 * * {}", day);
+        let next = weekday(timer::next_cron(&expression).unwrap());
+        assert_eq!(next.num_days_from_sunday(), day);
+    }
+    let weekdays = weekday(timer::next_cron("0 0 * * 1-5").unwrap());
+    assert!(!matches!(weekdays, Weekday::Sat | Weekday::Sun));
+    let weekend = weekday(timer::next_cron("0 0 * * 6-7").unwrap());
+    assert!(matches!(weekend, Weekday::Sat | Weekday::Sun));
+
+    // a range or step fires on the nearest of its days
+    let nearest = |days: &[u8]| {
+        days.iter()
+            .map(|day| timer::next_cron(&format!("0 0 * * {}", day)).unwrap())
+            .min()
+            .unwrap()
+    };
+    let daily = timer::next_cron("0 0 * * *").unwrap();
+    let every = timer::next_cron("0 0 * * 0-7").unwrap();
+    assert!(every.max(daily) - every.min(daily) < Duration::from_secs(1));
+    for (expression, days) in [
+        ("1-7/2", &[1, 3, 5, 0][..]),
+        ("0-6/2", &[0, 2, 4, 6][..]),
+        ("5/2", &[5, 0][..]),
+        ("0-7/1", &[0, 1, 2, 3, 4, 5, 6][..]),
+        ("3-7", &[3, 4, 5, 6, 0][..]),
+        ("0,7", &[0][..]),
+    ] {
+        let next = timer::next_cron(&format!("0 0 * * {}", expression)).unwrap();
+        let expected = nearest(days);
+        assert!(next.max(expected) - next.min(expected) < Duration::from_secs(1));
+    }
+}
+
+#[test]
+fn durations_without_components_are_rejected() {
+    assert!(timer::parse_duration("PT").is_err());
+    assert!(timer::parse_duration("P1DT").is_err());
+    assert!(timer::parse_duration("P").is_err());
+    assert_eq!(timer::parse_duration("P1D").unwrap(), DAY);
+    assert_eq!(timer::parse_duration("PT0S").unwrap(), Duration::ZERO);
+}
+
+#[test]
+fn far_durations_are_rejected() {
+    assert!(timer::deadline_after(Duration::MAX).is_err());
+}
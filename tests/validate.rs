@@ -0,0 +1,218 @@
+use donut::{
+    definition::{FlowDefinition, NodeDefinition, ProcedureDefinition},
+    procedure::Procedure,
+    validate::{Diagnostic, Severity},
+};
+
+fn build(source: &str) -> Procedure {
+    Procedure::from_definition(&ProcedureDefinition::from_yaml(source).unwrap()).unwrap()
+}
+
+#[test]
+fn sound_procedure_has_no_diagnostics() {
+    let procedure = build(
+        r#"
+name: sound
+start: [start]
+nodes:
+  - name: start
+  - name: retry
+    kind: exclusive_gateway
+  - name: end
+    script: set_complete()
+flows:
+  - { name: to_retry, source: start, target: retry }
+  - { name: again, source: retry, target: start, condition: 'attempts < 3' }
+  - { name: to_end, source: retry, target: end }
+"#,
+    );
+    assert_eq!(procedure.validate(), vec![]);
+}
+
+#[test]
+fn unreachable_node() {
+    let procedure = build(
+        r#"
+name: unreachable
+start: [start]
+nodes: [{ name: start }, { name: island }]
+"#,
+    );
+    let diagnostics = procedure.validate();
+    assert_eq!(
+        diagnostics,
+        vec![Diagnostic::Unreachable {
+            node: "island".to_string()
+        }]
+    );
+    assert_eq!(diagnostics[0].severity(), Severity::Warning);
+}
+
+#[test]
+fn foreign_flow() {
+    let mut procedure = build("{ name: own, nodes: [{ name: a }] }");
+    let other = build(
+        r#"
+name: other
+nodes: [{ name: b }, { name: c }]
+flows: [{ name: borrowed, source: b, target: c }]
+"#,
+    );
+    procedure
+        .flows
+        .insert("borrowed".to_string(), other.flows["borrowed"].clone());
+
+    assert_eq!(
+        procedure.validate(),
+        vec![
+            Diagnostic::ForeignFlow {
+                flow: "borrowed".to_string(),
+                node: Some("b".to_string()),
+            },
+            Diagnostic::ForeignFlow {
+                flow: "borrowed".to_string(),
+                node: Some("c".to_string()),
+            },
+        ]
+    );
+
+    // a dropped endpoint is foreign as well
+    drop(other);
+    assert!(procedure.validate().contains(&Diagnostic::ForeignFlow {
+        flow: "borrowed".to_string(),
+        node: None,
+    }));
+}
+
+#[test]
+fn dead_end() {
+    let procedure = build(
+        r#"
+name: dead_end
+start: [start]
+nodes:
+  - name: start
+  - name: stuck
+    script: x = 1
+  - name: done
+    script: set_complete()
+flows:
+  - { name: to_stuck, source: start, target: stuck }
+  - { name: to_done, source: start, target: done }
+"#,
+    );
+    let diagnostics = procedure.validate();
+    assert_eq!(
+        diagnostics,
+        vec![Diagnostic::DeadEnd {
+            node: "stuck".to_string()
+        }]
+    );
+    assert_eq!(diagnostics[0].severity(), Severity::Warning);
+}
+
+#[test]
+fn unguarded_cycle() {
+    let procedure = build(
+        r#"
+name: cycle
+start: [start]
+nodes: [{ name: start }, { name: ping }, { name: pong }]
+flows:
+  - { name: to_ping, source: start, target: ping }
+  - { name: serve, source: ping, target: pong }
+  - { name: back, source: pong, target: ping }
+"#,
+    );
+    assert_eq!(
+        procedure.validate(),
+        vec![Diagnostic::UnguardedCycle {
+            nodes: vec!["ping".to_string(), "pong".to_string()]
+        }]
+    );
+}
+
+#[test]
+fn missing_and_unknown_start() {
+    // every node has an incoming flow, so none qualifies as start
+    let procedure = build(
+        r#"
+name: loop
+nodes: [{ name: a }, { name: b }]
+flows:
+  - { name: there, source: a, target: b, condition: 'true' }
+  - { name: back, source: b, target: a, condition: 'true' }
+"#,
+    );
+    assert_eq!(procedure.validate(), vec![Diagnostic::MissingStart]);
+
+    let mut procedure = build("{ name: ghost, start: [a], nodes: [{ name: a }] }");
+    procedure.start.push("ghost".to_string());
+    let diagnostics = procedure.validate();
+    assert_eq!(
+        diagnostics,
+        vec![Diagnostic::UnknownStart {
+            node: "ghost".to_string()
+        }]
+    );
+    assert_eq!(diagnostics[0].severity(), Severity::Error);
+}
+
+#[test]
+fn script_syntax_error() {
+    let procedure = build(
+        r#"
+name: syntax
+start: [start]
+nodes:
+  - name: start
+    script: 'set_state('
+  - name: end
+flows:
+  - { name: to_end, source: start, target: end, condition: 'a ==' }
+"#,
+    );
+    let elements: Vec<String> = procedure
+        .validate()
+        .into_iter()
+        .map(|diagnostic| match diagnostic {
+            Diagnostic::SyntaxError { element, .. } => element,
+            diagnostic => panic!("unexpected {:?}", diagnostic),
+        })
+        .collect();
+    assert_eq!(elements, vec!["start".to_string(), "to_end".to_string()]);
+}
+
+#[test]
+fn long_chains_validate_on_a_small_stack() {
+    let count = 20_000;
+    let name = |index: usize| format!("n{}", index);
+    let definition = ProcedureDefinition {
+        name: "chain".to_string(),
+        nodes: (0..count)
+            .map(|index| NodeDefinition {
+                name: name(index),
+                ..Default::default()
+            })
+            .collect(),
+        flows: (1..count)
+            .map(|index| FlowDefinition {
+                name: format!("f{}", index),
+                source: name(index - 1),
+                target: name(index),
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    };
+    let procedure = Procedure::from_definition(&definition).unwrap();
+
+    // the stack size of a tokio worker
+    let diagnostics = std::thread::Builder::new()
+        .stack_size(2 * 1024 * 1024)
+        .spawn(move || procedure.validate())
+        .unwrap()
+        .join()
+        .unwrap();
+    assert_eq!(diagnostics, vec![]);
+}